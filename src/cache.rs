@@ -0,0 +1,101 @@
+use checkpoint::Fnv;
+use image::{DynamicImage, GenericImage, GrayImage};
+use settings::{GenSettings, MaskSource};
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Outputs of finished runs kept in a directory under hash of the input pixels and settings, so runs
+/// with unchanged input reuse them. Every output of a run is stored as `<key>_<index>.<extension>`.
+pub struct Cache {
+    dir: PathBuf,
+    key: u64,
+}
+
+impl Cache {
+    /// Cache entry of the input image, its mask and settings. File names and the cache directory are
+    /// left out of the key, so the same pixels share the entry under any name.
+    pub fn new(dir: &str, img: &DynamicImage, mask: Option<&GrayImage>, settings: &GenSettings) -> Cache {
+        let mut hasher = Fnv::default();
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write(format!("{:?} {:?}", img.dimensions(), img.color()).as_bytes());
+        hasher.write(&img.raw_pixels());
+        if let Some(mask) = mask {
+            hasher.write(mask);
+        }
+        let mut settings = settings.clone();
+        settings.export.texture = None;
+        settings.previous = None;
+        settings.split = None;
+        settings.cache_dir = None;
+        if let Some(MaskSource::Image(ref mut filename)) = settings.mask {
+            filename.clear();
+        }
+        hasher.write(format!("{:?}", settings).as_bytes());
+        Cache {
+            dir: PathBuf::from(dir),
+            key: hasher.finish(),
+        }
+    }
+
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Copy cached outputs to given files, returns false without copying when any of them is missing
+    pub fn restore(&self, outputs: &[String]) -> io::Result<bool> {
+        let entries: Vec<PathBuf> = outputs.iter().enumerate().map(|(index, output)| self.entry(index, output)).collect();
+        if !entries.iter().all(|entry| entry.is_file()) {
+            return Ok(false);
+        }
+        for (entry, output) in entries.iter().zip(outputs) {
+            fs::copy(entry, output)?;
+        }
+        Ok(true)
+    }
+
+    /// Copy finished outputs into the cache, every one is renamed into place once it is whole,
+    /// so other runs never see it partially written
+    pub fn store(&self, outputs: &[String]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        for (index, output) in outputs.iter().enumerate() {
+            let entry = self.entry(index, output);
+            let part = entry.with_extension(format!("{}.part", process::id()));
+            fs::copy(output, &part)?;
+            fs::rename(&part, &entry)?;
+        }
+        Ok(())
+    }
+
+    fn entry(&self, index: usize, output: &str) -> PathBuf {
+        let extension = Path::new(output).extension().and_then(|extension| extension.to_str()).unwrap_or("bin");
+        self.dir.join(format!("{:016x}_{}.{}", self.key, index, extension))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Luma};
+
+    fn key(mask_name: &str, mask_value: u8) -> u64 {
+        let img = DynamicImage::ImageLuma8(ImageBuffer::from_fn(4, 4, |x, y| Luma([(x * 16 + y) as u8])));
+        let mask: GrayImage = ImageBuffer::from_pixel(4, 4, Luma([mask_value]));
+        let mut settings = GenSettings::new();
+        settings.mask = Some(MaskSource::Image(String::from(mask_name)));
+        settings.cache_dir = Some(String::from(mask_name));
+        Cache::new("cache", &img, Some(&mask), &settings).key()
+    }
+
+    #[test]
+    fn key_ignores_file_names() {
+        assert_eq!(key("mask.png", 255), key("other/renamed.png", 255));
+    }
+
+    #[test]
+    fn key_depends_on_mask_pixels() {
+        assert_ne!(key("mask.png", 255), key("mask.png", 0));
+    }
+}
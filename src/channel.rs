@@ -0,0 +1,74 @@
+use image::{ColorType, DynamicImage, GenericImage, GrayImage, ImageBuffer, ImageLuma8, ImageLumaA8, ImageRgb8, ImageRgba8, Luma, LumaA, Rgb, Rgba};
+
+/// Value of the input pixel used as height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelSource {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    /// weighted sum of color channels
+    Luminance,
+    /// largest of color channels
+    Max,
+}
+
+impl ChannelSource {
+    pub fn from_name(name: &str) -> Option<ChannelSource> {
+        match name.trim().to_lowercase().as_str() {
+            "r" | "red" => Some(ChannelSource::Red),
+            "g" | "green" => Some(ChannelSource::Green),
+            "b" | "blue" => Some(ChannelSource::Blue),
+            "a" | "alpha" => Some(ChannelSource::Alpha),
+            "l" | "luma" | "luminance" => Some(ChannelSource::Luminance),
+            "max" => Some(ChannelSource::Max),
+            _ => None,
+        }
+    }
+
+    /// Value of the pixel selected by this source
+    pub fn value(self, pixel: &Rgba<u8>) -> u8 {
+        let [r, g, b, a] = pixel.data;
+        match self {
+            ChannelSource::Red => r,
+            ChannelSource::Green => g,
+            ChannelSource::Blue => b,
+            ChannelSource::Alpha => a,
+            // same weights as the image crate uses for grayscale conversion
+            ChannelSource::Luminance => ((2126 * u32::from(r) + 7152 * u32::from(g) + 722 * u32::from(b) + 5000) / 10000) as u8,
+            ChannelSource::Max => r.max(g).max(b),
+        }
+    }
+}
+
+/// Extract one channel of the image into a grayscale image
+pub fn extract(img: &DynamicImage, source: ChannelSource) -> DynamicImage {
+    let rgba = img.to_rgba();
+    let (width, height) = img.dimensions();
+    ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| Luma([source.value(rgba.get_pixel(x, y))])))
+}
+
+/// Channels stored in the image, in their order
+pub fn image_channels(img: &DynamicImage) -> Vec<ChannelSource> {
+    match img.color() {
+        ColorType::Gray(_) => vec![ChannelSource::Red],
+        ColorType::GrayA(_) => vec![ChannelSource::Red, ChannelSource::Alpha],
+        ColorType::RGB(_) | ColorType::Palette(_) => vec![ChannelSource::Red, ChannelSource::Green, ChannelSource::Blue],
+        ColorType::RGBA(_) => vec![ChannelSource::Red, ChannelSource::Green, ChannelSource::Blue, ChannelSource::Alpha],
+    }
+}
+
+/// Combine grayscale images of the same size into one image with as many channels,
+/// one to four channels are supported
+pub fn combine(channels: &[GrayImage]) -> DynamicImage {
+    let (width, height) = channels[0].dimensions();
+    let value = |channel: usize, x: u32, y: u32| channels[channel].get_pixel(x, y).data[0];
+    match channels.len() {
+        1 => ImageLuma8(channels[0].clone()),
+        2 => ImageLumaA8(ImageBuffer::from_fn(width, height, |x, y| LumaA([value(0, x, y), value(1, x, y)]))),
+        3 => ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| Rgb([value(0, x, y), value(1, x, y), value(2, x, y)]))),
+        _ => ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| {
+            Rgba([value(0, x, y), value(1, x, y), value(2, x, y), value(3, x, y)])
+        })),
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+const MAGIC: &[u8; 4] = b"DFCP";
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Finished chunks of distance search kept in a file, so a killed run can skip them when resumed.
+/// File starts with a key of the run, every chunk is stored as its index, number of values and
+/// the values, records cut by killing the run are dropped.
+pub struct Checkpoint {
+    path: String,
+    writer: Mutex<BufWriter<File>>,
+    /// values of chunks finished by previous run
+    done: HashMap<usize, Vec<f32>>,
+}
+
+/// FNV-1a hash, stable between runs and platforms
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(FNV_OFFSET)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hash of the file contents followed by given text, e.g. debug output of settings
+pub fn hash_file(filename: &str, text: &str) -> io::Result<u64> {
+    let mut hasher = Fnv::default();
+    let mut reader = BufReader::new(File::open(filename)?);
+    let mut buffer = [0u8; 1 << 16];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+    }
+    hasher.write(text.as_bytes());
+    Ok(hasher.finish())
+}
+
+impl Checkpoint {
+    /// Open checkpoint file of the run with given key. When resuming, chunks stored by previous run
+    /// with the same key are loaded and kept, otherwise the file starts empty.
+    pub fn open(path: &str, key: u64, resume: bool) -> io::Result<Checkpoint> {
+        let previous = match resume {
+            true => load(path, key).ok(),
+            false => None,
+        };
+        let (file, done) = match previous {
+            Some((done, length)) => {
+                // drop record cut by killing the previous run
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(length)?;
+                file.seek(SeekFrom::End(0))?;
+                (file, done)
+            }
+            None => {
+                let mut file = File::create(path)?;
+                file.write_all(MAGIC)?;
+                file.write_all(&key.to_le_bytes())?;
+                (file, HashMap::new())
+            }
+        };
+        Ok(Checkpoint {
+            path: String::from(path),
+            writer: Mutex::new(BufWriter::new(file)),
+            done,
+        })
+    }
+
+    /// Number of chunks finished by previous run
+    pub fn resumed(&self) -> usize {
+        self.done.len()
+    }
+
+    /// Values of the chunk finished by previous run
+    pub fn get(&self, chunk: usize) -> Option<&[f32]> {
+        self.done.get(&chunk).map(|values| values.as_slice())
+    }
+
+    /// Store values of finished chunk, they are written to the file right away
+    pub fn store(&self, chunk: usize, values: &[f32]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&(chunk as u32).to_le_bytes())?;
+        writer.write_all(&(values.len() as u32).to_le_bytes())?;
+        for value in values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Delete the file once the run is finished
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.writer);
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Chunks stored in the file with given key and length of the file up to the last whole record
+fn load(path: &str, key: u64) -> io::Result<(HashMap<usize, Vec<f32>>, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC || header[4..] != key.to_le_bytes() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "checkpoint of another run"));
+    }
+    let mut done = HashMap::new();
+    let mut length = header.len() as u64;
+    let mut record = [0u8; 8];
+    while reader.read_exact(&mut record).is_ok() {
+        let chunk = u32::from_le_bytes([record[0], record[1], record[2], record[3]]) as usize;
+        let count = u32::from_le_bytes([record[4], record[5], record[6], record[7]]) as usize;
+        let mut bytes = vec![0u8; count * 4];
+        if reader.read_exact(&mut bytes).is_err() {
+            break;
+        }
+        let values = bytes.chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        done.insert(chunk, values);
+        length += (record.len() + bytes.len()) as u64;
+    }
+    Ok((done, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn temp(name: &str) -> String {
+        env::temp_dir().join(format!("distance_field_{}_{}.ckpt", process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn resume_keeps_whole_records_and_drops_cut_one() {
+        let path = temp("resume");
+        let checkpoint = Checkpoint::open(&path, 7, false).unwrap();
+        checkpoint.store(3, &[1.0, 2.5]).unwrap();
+        checkpoint.store(0, &[]).unwrap();
+        drop(checkpoint);
+        let length = fs::metadata(&path).unwrap().len();
+        // record of chunk 5 cut in its values
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&5u32.to_le_bytes()).unwrap();
+        file.write_all(&2u32.to_le_bytes()).unwrap();
+        file.write_all(&1f32.to_le_bytes()).unwrap();
+        drop(file);
+
+        let checkpoint = Checkpoint::open(&path, 7, true).unwrap();
+        assert_eq!(checkpoint.resumed(), 2);
+        assert_eq!(checkpoint.get(3), Some(&[1.0, 2.5][..]));
+        assert_eq!(checkpoint.get(0), Some(&[][..]));
+        assert_eq!(checkpoint.get(5), None);
+        assert_eq!(fs::metadata(&path).unwrap().len(), length);
+        checkpoint.store(5, &[4.0]).unwrap();
+        drop(checkpoint);
+
+        let checkpoint = Checkpoint::open(&path, 7, true).unwrap();
+        assert_eq!(checkpoint.resumed(), 3);
+        assert_eq!(checkpoint.get(5), Some(&[4.0][..]));
+        checkpoint.remove().unwrap();
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn checkpoint_of_another_run_or_without_resume_starts_empty() {
+        let path = temp("other");
+        let checkpoint = Checkpoint::open(&path, 7, false).unwrap();
+        checkpoint.store(1, &[1.0]).unwrap();
+        drop(checkpoint);
+        assert!(load(&path, 8).is_err());
+        assert_eq!(Checkpoint::open(&path, 8, true).unwrap().resumed(), 0);
+
+        let checkpoint = Checkpoint::open(&path, 8, false).unwrap();
+        checkpoint.store(1, &[1.0]).unwrap();
+        drop(checkpoint);
+        let checkpoint = Checkpoint::open(&path, 8, false).unwrap();
+        assert_eq!(checkpoint.resumed(), 0);
+        checkpoint.remove().unwrap();
+    }
+
+    #[test]
+    fn hash_depends_on_contents_and_text() {
+        let path = temp("hash");
+        fs::write(&path, b"contents").unwrap();
+        let hash = hash_file(&path, "settings").unwrap();
+        assert_eq!(hash, hash_file(&path, "settings").unwrap());
+        assert_ne!(hash, hash_file(&path, "other settings").unwrap());
+        fs::write(&path, b"contents!").unwrap();
+        assert_ne!(hash, hash_file(&path, "settings").unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
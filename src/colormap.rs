@@ -0,0 +1,79 @@
+/// Colormaps for visualising distances, values go from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Colormap {
+    Grayscale,
+    Viridis,
+    Magma,
+    Jet,
+}
+
+// control points of the colormaps, position and color
+const GRAYSCALE: [(f32, [f32; 3]); 2] = [(0.0, [0.0, 0.0, 0.0]), (1.0, [1.0, 1.0, 1.0])];
+
+const VIRIDIS: [(f32, [f32; 3]); 9] = [
+    (0.0, [0.267, 0.005, 0.329]),
+    (0.125, [0.283, 0.141, 0.458]),
+    (0.25, [0.254, 0.265, 0.530]),
+    (0.375, [0.207, 0.372, 0.553]),
+    (0.5, [0.164, 0.471, 0.558]),
+    (0.625, [0.128, 0.567, 0.551]),
+    (0.75, [0.135, 0.659, 0.518]),
+    (0.875, [0.369, 0.789, 0.383]),
+    (1.0, [0.993, 0.906, 0.144]),
+];
+
+const MAGMA: [(f32, [f32; 3]); 9] = [
+    (0.0, [0.001, 0.000, 0.014]),
+    (0.125, [0.113, 0.065, 0.277]),
+    (0.25, [0.316, 0.072, 0.485]),
+    (0.375, [0.492, 0.133, 0.507]),
+    (0.5, [0.716, 0.215, 0.475]),
+    (0.625, [0.868, 0.288, 0.409]),
+    (0.75, [0.967, 0.439, 0.360]),
+    (0.875, [0.994, 0.624, 0.427]),
+    (1.0, [0.987, 0.991, 0.750]),
+];
+
+const JET: [(f32, [f32; 3]); 6] = [
+    (0.0, [0.0, 0.0, 0.5]),
+    (0.125, [0.0, 0.0, 1.0]),
+    (0.375, [0.0, 1.0, 1.0]),
+    (0.625, [1.0, 1.0, 0.0]),
+    (0.875, [1.0, 0.0, 0.0]),
+    (1.0, [0.5, 0.0, 0.0]),
+];
+
+impl Colormap {
+    pub fn from_name(name: &str) -> Option<Colormap> {
+        match name.trim().to_lowercase().as_str() {
+            "grayscale" | "gray" => Some(Colormap::Grayscale),
+            "viridis" => Some(Colormap::Viridis),
+            "magma" => Some(Colormap::Magma),
+            "jet" => Some(Colormap::Jet),
+            _ => None,
+        }
+    }
+
+    /// Color of given value, values outside of 0-1 are clamped
+    pub fn map(self, value: f32) -> [f32; 3] {
+        let points: &[(f32, [f32; 3])] = match self {
+            Colormap::Grayscale => &GRAYSCALE,
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Jet => &JET,
+        };
+        let value = if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+        for pair in points.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            if value <= end.0 {
+                let t = (value - start.0) / (end.0 - start.0);
+                return [
+                    start.1[0] + (end.1[0] - start.1[0]) * t,
+                    start.1[1] + (end.1[1] - start.1[1]) * t,
+                    start.1[2] + (end.1[2] - start.1[2]) * t,
+                ];
+            }
+        }
+        points[points.len() - 1].1
+    }
+}
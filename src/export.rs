@@ -0,0 +1,385 @@
+use settings::MeshFormat;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use trimesh::TriMesh;
+
+const MATERIAL_NAME: &str = "distance_field";
+
+/// Write mesh to file in given format through a buffered writer.
+pub fn write_mesh(mesh: &TriMesh, filename: &str, format: MeshFormat) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    match format {
+        MeshFormat::Obj => write_obj(mesh, &mut writer, None)?,
+        MeshFormat::Stl => write_stl(mesh, &mut writer)?,
+        MeshFormat::Ply => write_ply(mesh, &mut writer)?,
+        MeshFormat::Glb => write_glb(mesh, &mut writer)?,
+    };
+    writer.flush()
+}
+
+/// Write OBJ together with a material file next to it, which uses given image as diffuse texture.
+pub fn write_obj_textured(mesh: &TriMesh, filename: &str, texture: &str) -> io::Result<()> {
+    let obj_path = Path::new(filename);
+    let mtl_path = obj_path.with_extension("mtl");
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    write_mtl(&mut mtl, &texture_path(obj_path, texture))?;
+    mtl.flush()?;
+
+    let mtl_name = mtl_path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut writer = BufWriter::new(File::create(obj_path)?);
+    write_obj(mesh, &mut writer, Some(&mtl_name))?;
+    writer.flush()
+}
+
+/// Wavefront OBJ, ASCII. Normals and uvs are written when the mesh has them,
+/// colors use the common `v x y z r g b` extension.
+pub fn write_obj<W: Write>(mesh: &TriMesh, w: &mut W, mtllib: Option<&str>) -> io::Result<()> {
+    if let Some(mtllib) = mtllib {
+        writeln!(w, "mtllib {}", mtllib)?;
+    }
+    if mesh.colors.is_empty() {
+        for vert in &mesh.verts {
+            writeln!(w, "v {} {} {}", vert.x, vert.y, vert.z)?;
+        }
+    } else {
+        for (vert, color) in mesh.verts.iter().zip(mesh.colors.iter()) {
+            writeln!(w, "v {} {} {} {} {} {}", vert.x, vert.y, vert.z, color[0], color[1], color[2])?;
+        }
+    }
+    for uv in &mesh.uvs {
+        writeln!(w, "vt {} {}", uv.0, uv.1)?;
+    }
+    for normal in &mesh.normals {
+        writeln!(w, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+    }
+    if mtllib.is_some() {
+        writeln!(w, "usemtl {}", MATERIAL_NAME)?;
+    }
+    // obj indices start at 1, all attributes share vertex indices
+    let has_uvs = !mesh.uvs.is_empty();
+    let has_normals = !mesh.normals.is_empty();
+    for face in &mesh.faces {
+        let (a, b, c) = (face[0] + 1, face[1] + 1, face[2] + 1);
+        match (has_uvs, has_normals) {
+            (true, true) => writeln!(w, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?,
+            (true, false) => writeln!(w, "f {0}/{0} {1}/{1} {2}/{2}", a, b, c)?,
+            (false, true) => writeln!(w, "f {0}//{0} {1}//{1} {2}//{2}", a, b, c)?,
+            (false, false) => writeln!(w, "f {} {} {}", a, b, c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Material library with a single textured material.
+pub fn write_mtl<W: Write>(w: &mut W, texture: &str) -> io::Result<()> {
+    writeln!(w, "newmtl {}", MATERIAL_NAME)?;
+    writeln!(w, "Ka 0 0 0")?;
+    writeln!(w, "Kd 1 1 1")?;
+    writeln!(w, "Ks 0 0 0")?;
+    writeln!(w, "illum 1")?;
+    writeln!(w, "map_Kd {}", texture)
+}
+
+/// Texture path as seen from the material file, relative when both files share a directory.
+fn texture_path(obj_path: &Path, texture: &str) -> String {
+    let texture = Path::new(texture);
+    let absolute = |path: &Path| -> PathBuf {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf())
+        }
+    };
+    let obj_dir = absolute(obj_path.parent().unwrap_or_else(|| Path::new("")));
+    let texture_abs = absolute(texture);
+    match texture_abs.strip_prefix(&obj_dir) {
+        Ok(relative) => relative.to_string_lossy().into_owned(),
+        Err(_) => texture_abs.to_string_lossy().into_owned(),
+    }
+}
+
+/// Binary STL.
+pub fn write_stl<W: Write>(mesh: &TriMesh, w: &mut W) -> io::Result<()> {
+    let mut header = [0u8; 80];
+    let text = b"distance_field binary STL";
+    header[..text.len()].copy_from_slice(text);
+    w.write_all(&header)?;
+    write_u32(w, mesh.faces.len() as u32)?;
+    for face in &mesh.faces {
+        let normal = mesh.face_normal(face);
+        write_f32s(w, &[normal.x, normal.y, normal.z])?;
+        for index in face {
+            let vert = &mesh.verts[*index as usize];
+            write_f32s(w, &[vert.x, vert.y, vert.z])?;
+        }
+        // attribute byte count
+        w.write_all(&[0, 0])?;
+    }
+    Ok(())
+}
+
+/// Binary little endian PLY, with 8-bit vertex colors when the mesh has them.
+pub fn write_ply<W: Write>(mesh: &TriMesh, w: &mut W) -> io::Result<()> {
+    let has_colors = !mesh.colors.is_empty();
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    writeln!(w, "comment distance_field heightfield")?;
+    writeln!(w, "element vertex {}", mesh.verts.len())?;
+    writeln!(w, "property float x")?;
+    writeln!(w, "property float y")?;
+    writeln!(w, "property float z")?;
+    if has_colors {
+        writeln!(w, "property uchar red")?;
+        writeln!(w, "property uchar green")?;
+        writeln!(w, "property uchar blue")?;
+    }
+    writeln!(w, "element face {}", mesh.faces.len())?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "end_header")?;
+    for (i, vert) in mesh.verts.iter().enumerate() {
+        write_f32s(w, &[vert.x, vert.y, vert.z])?;
+        if has_colors {
+            let color = mesh.colors[i];
+            w.write_all(&[(color[0] * 255.0).round() as u8, (color[1] * 255.0).round() as u8, (color[2] * 255.0).round() as u8])?;
+        }
+    }
+    for face in &mesh.faces {
+        w.write_all(&[3])?;
+        for index in face {
+            write_u32(w, *index)?;
+        }
+    }
+    Ok(())
+}
+
+/// glTF 2.0 binary container with a single mesh.
+pub fn write_glb<W: Write>(mesh: &TriMesh, w: &mut W) -> io::Result<()> {
+    // GL constants used in the json chunk
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+    // every accessor gets its own buffer view, laid out one after another in the binary chunk
+    let mut accessors: Vec<String> = Vec::new();
+    let mut views: Vec<String> = Vec::new();
+    let mut bin_len = 0;
+    let mut add_accessor = |byte_len: usize, target: u32, accessor: String| {
+        views.push(format!(
+            "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{}}}",
+            bin_len, byte_len, target
+        ));
+        accessors.push(format!("{{\"bufferView\":{},{}}}", accessors.len(), accessor));
+        bin_len += byte_len;
+        accessors.len() - 1
+    };
+
+    let (min, max) = mesh.bounds();
+    let position = add_accessor(
+        mesh.verts.len() * 12,
+        ARRAY_BUFFER,
+        format!(
+            "\"componentType\":{},\"count\":{},\"type\":\"VEC3\",\"min\":[{},{},{}],\"max\":[{},{},{}]",
+            FLOAT,
+            mesh.verts.len(),
+            min.x,
+            min.y,
+            min.z,
+            max.x,
+            max.y,
+            max.z
+        ),
+    );
+    let mut attributes = format!("\"POSITION\":{}", position);
+    if !mesh.colors.is_empty() {
+        let color = add_accessor(
+            mesh.colors.len() * 12,
+            ARRAY_BUFFER,
+            format!("\"componentType\":{},\"count\":{},\"type\":\"VEC3\"", FLOAT, mesh.colors.len()),
+        );
+        attributes.push_str(&format!(",\"COLOR_0\":{}", color));
+    }
+    let indices = add_accessor(
+        mesh.faces.len() * 12,
+        ELEMENT_ARRAY_BUFFER,
+        format!("\"componentType\":{},\"count\":{},\"type\":\"SCALAR\"", UNSIGNED_INT, mesh.faces.len() * 3),
+    );
+
+    let json = format!(
+        concat!(
+            "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"distance_field\"}},",
+            "\"scene\":0,\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],",
+            "\"meshes\":[{{\"primitives\":[{{\"attributes\":{{{}}},\"indices\":{}}}]}}],",
+            "\"accessors\":[{}],\"bufferViews\":[{}],\"buffers\":[{{\"byteLength\":{}}}]}}"
+        ),
+        attributes,
+        indices,
+        accessors.join(","),
+        views.join(","),
+        bin_len
+    );
+    // chunks have to be aligned to 4 bytes, json is padded with spaces, binary data is always aligned
+    let mut json = json.into_bytes();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let total_len = 12 + 8 + json.len() + 8 + bin_len;
+
+    // header
+    w.write_all(b"glTF")?;
+    write_u32(w, 2)?;
+    write_u32(w, total_len as u32)?;
+    // json chunk
+    write_u32(w, json.len() as u32)?;
+    w.write_all(b"JSON")?;
+    w.write_all(&json)?;
+    // binary chunk, same order as accessors
+    write_u32(w, bin_len as u32)?;
+    w.write_all(b"BIN\0")?;
+    for vert in &mesh.verts {
+        write_f32s(w, &[vert.x, vert.y, vert.z])?;
+    }
+    // glTF vertex colors are linear
+    for color in &mesh.colors {
+        write_f32s(w, &color.map(srgb_to_linear))?;
+    }
+    for face in &mesh.faces {
+        for index in face {
+            write_u32(w, *index)?;
+        }
+    }
+    Ok(())
+}
+
+/// Linear value of srgb color component
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn write_u32<W: Write>(w: &mut W, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_f32s<W: Write>(w: &mut W, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        w.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vec3::Vec3;
+
+    /// Unit square of two triangles facing up
+    fn square() -> TriMesh {
+        let mut mesh = TriMesh::new();
+        mesh.verts = vec![
+            Vec3::new((0.0, 0.0, 0.5)),
+            Vec3::new((1.0, 0.0, 0.5)),
+            Vec3::new((1.0, 1.0, 1.5)),
+            Vec3::new((0.0, 1.0, 1.5)),
+        ];
+        mesh.faces = vec![[0, 1, 2], [0, 2, 3]];
+        mesh
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    fn f32s_at(bytes: &[u8], offset: usize, count: usize) -> Vec<f32> {
+        (0..count).map(|i| f32::from_bits(u32_at(bytes, offset + 4 * i))).collect()
+    }
+
+    #[test]
+    fn obj_has_one_based_faces_with_uvs_and_normals() {
+        let mut mesh = square();
+        mesh.uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        mesh.compute_normals();
+        let mut bytes = Vec::new();
+        write_obj(&mesh, &mut bytes, Some("square.mtl")).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "mtllib square.mtl");
+        assert_eq!(lines[1], "v 0 0 0.5");
+        assert_eq!(lines.iter().filter(|line| line.starts_with("vt ")).count(), 4);
+        assert_eq!(lines.iter().filter(|line| line.starts_with("vn ")).count(), 4);
+        assert!(lines.contains(&"usemtl distance_field"));
+        assert_eq!(&lines[lines.len() - 2..], &["f 1/1/1 2/2/2 3/3/3", "f 1/1/1 3/3/3 4/4/4"]);
+    }
+
+    #[test]
+    fn stl_has_every_face_with_its_normal() {
+        let mesh = square();
+        let mut bytes = Vec::new();
+        write_stl(&mesh, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 80 + 4 + 2 * 50);
+        assert_eq!(u32_at(&bytes, 80), 2);
+        for (i, face) in mesh.faces.iter().enumerate() {
+            let values = f32s_at(&bytes, 84 + i * 50, 12);
+            let normal = mesh.face_normal(face);
+            assert_eq!(&values[..3], &[normal.x, normal.y, normal.z]);
+            for (corner, index) in face.iter().enumerate() {
+                let vert = &mesh.verts[*index as usize];
+                assert_eq!(&values[3 + corner * 3..6 + corner * 3], &[vert.x, vert.y, vert.z]);
+            }
+        }
+    }
+
+    #[test]
+    fn ply_header_matches_binary_body() {
+        let mut mesh = square();
+        mesh.colors = vec![[0.0, 0.5, 1.0]; 4];
+        let mut bytes = Vec::new();
+        write_ply(&mesh, &mut bytes).unwrap();
+        let end = b"end_header\n";
+        let body = bytes.windows(end.len()).position(|window| window == end).unwrap() + end.len();
+        let header = String::from_utf8(bytes[..body].to_vec()).unwrap();
+        assert!(header.contains("element vertex 4\n"));
+        assert!(header.contains("property uchar red\n"));
+        assert!(header.contains("element face 2\n"));
+        // vertices of 3 floats and 3 colors, faces of count and 3 indices
+        assert_eq!(bytes.len() - body, 4 * 15 + 2 * 13);
+        assert_eq!(f32s_at(&bytes, body, 3), vec![0.0, 0.0, 0.5]);
+        assert_eq!(&bytes[body + 12..body + 15], &[0, 128, 255]);
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned_and_hold_the_mesh() {
+        let mut mesh = square();
+        mesh.colors = vec![[1.0, 0.5, 0.0]; 4];
+        let mut bytes = Vec::new();
+        write_glb(&mesh, &mut bytes).unwrap();
+        assert_eq!(&bytes[..4], b"glTF");
+        assert_eq!(u32_at(&bytes, 4), 2);
+        assert_eq!(u32_at(&bytes, 8) as usize, bytes.len());
+
+        let json_len = u32_at(&bytes, 12) as usize;
+        assert_eq!(json_len % 4, 0);
+        assert_eq!(&bytes[16..20], b"JSON");
+        let json = String::from_utf8(bytes[20..20 + json_len].to_vec()).unwrap();
+        assert!(json.contains("\"min\":[0,0,0.5],\"max\":[1,1,1.5]"));
+        assert!(json.contains("\"COLOR_0\""));
+        assert!(json.contains(&format!("\"buffers\":[{{\"byteLength\":{}}}]", 4 * 12 + 4 * 12 + 2 * 12)));
+
+        let bin = 20 + json_len;
+        assert_eq!(u32_at(&bytes, bin) as usize, bytes.len() - bin - 8);
+        assert_eq!(&bytes[bin + 4..bin + 8], b"BIN\0");
+        let data = bin + 8;
+        assert_eq!(f32s_at(&bytes, data + 2 * 12, 3), vec![1.0, 1.0, 1.5]);
+        // colors are linear
+        let color = f32s_at(&bytes, data + 4 * 12, 3);
+        assert_eq!((color[0], color[2]), (1.0, 0.0));
+        assert!((color[1] - 0.214).abs() < 1e-3);
+        let indices: Vec<u32> = (0..6).map(|i| u32_at(&bytes, data + 8 * 12 + 4 * i)).collect();
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+}
@@ -1,24 +1,220 @@
+use checkpoint::Checkpoint;
 use extrema::Extrema;
-use image::{ImageBuffer, Luma};
+use image::{GrayImage, ImageBuffer, Luma, Rgba};
 use mesh::Mesh;
 use rayon::prelude::*;
+use remap;
 use settings::{CaptureHeight, GenSettings, ImgRepeat};
 use std::cmp::Ordering;
 use std::f32;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
 use vec3::Vec3;
+use volume::Volume;
 
-pub fn generate_distances(mesh: &Mesh, settings: &GenSettings, ext: &Extrema) -> Vec<Dist> {
-    let capture_height = match settings.height_setting {
-        CaptureHeight::Generated => (ext.max as f32 / 255.0) * settings.radius as f32 * settings.img_height_mult,
-        CaptureHeight::UserDefined(val) => (val as f32 / 255.0) * settings.radius as f32 * settings.img_height_mult,
+/// One distance field of a run, distances are searched within `radius` around the pixel
+/// from plane at `height`
+#[derive(Debug, Clone, Copy)]
+pub struct Layer {
+    pub radius: usize,
+    pub height: u8,
+}
+
+/// Rectangle of pixels, y goes down like in the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    /// Whole image of given dimensions
+    pub fn whole(dim: (usize, usize)) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width: dim.0,
+            height: dim.1,
+        }
+    }
+
+    /// Parse `X,Y,WIDTH,HEIGHT`, region has to have some pixels
+    pub fn parse(text: &str) -> Option<Region> {
+        let values: Vec<usize> = text.split(',').map(|value| value.trim().parse::<usize>()).collect::<Result<_, _>>().ok()?;
+        match values.as_slice() {
+            [x, y, width, height] if *width > 0 && *height > 0 => Some(Region {
+                x: *x,
+                y: *y,
+                width: *width,
+                height: *height,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Part of the region inside of the image
+    pub fn clipped(&self, dim: (usize, usize)) -> Region {
+        let (x, y) = (self.x.min(dim.0), self.y.min(dim.1));
+        Region {
+            x,
+            y,
+            width: (self.x + self.width).min(dim.0) - x,
+            height: (self.y + self.height).min(dim.1) - y,
+        }
+    }
+
+    /// Region grown by `size` on every side and clipped to the image. With repeated image
+    /// it spans the whole image along axes where it would cross the edge.
+    pub fn grown(&self, size: usize, dim: (usize, usize), repeat: ImgRepeat) -> Region {
+        let wraps = |start: usize, length: usize, max: usize| match repeat {
+            ImgRepeat::Repeat => start < size || start + length + size > max,
+            ImgRepeat::Clamp => false,
+        };
+        let grow = |start: usize, length: usize, max: usize| match wraps(start, length, max) {
+            true => (0, max),
+            false => (start.saturating_sub(size), start + length + size - start.saturating_sub(size)),
+        };
+        let (x, width) = grow(self.x, self.width, dim.0);
+        let (y, height) = grow(self.y, self.height, dim.1);
+        Region { x, y, width, height }.clipped(dim)
+    }
+}
+
+/// Layers computed for given settings, every radius with every capture height
+pub fn layers(settings: &GenSettings, ext: &Extrema) -> Vec<Layer> {
+    let heights = match settings.height_setting {
+        CaptureHeight::Generated => vec![ext.max],
+        CaptureHeight::UserDefined(val) => vec![val],
+        CaptureHeight::Multiple(ref values) => values.clone(),
     };
-    //generate spiral for generating distances
+    let mut layers = Vec::new();
+    for radius in settings.layer_radii() {
+        for height in &heights {
+            layers.push(Layer { radius, height: *height });
+        }
+    }
+    layers
+}
+
+/// Shared flag for stopping a running search from another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/// Search was stopped by its cancel token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cancelled;
+
+/// Progress reporting, cancellation and checkpointing of distance search, all are optional
+#[derive(Default)]
+pub struct Monitor<'a> {
+    /// called with fraction of done chunks from worker threads
+    pub progress: Option<&'a (dyn Fn(f32) + Sync)>,
+    /// checked before every chunk
+    pub cancel: Option<CancelToken>,
+    /// finished chunks are stored in it and chunks it already has are not searched again
+    pub checkpoint: Option<&'a Checkpoint>,
+}
+
+/// Distances of the first layer, when there are more of them
+pub fn generate_distances(mesh: &Mesh, settings: &GenSettings, ext: &Extrema) -> Vec<Dist> {
+    generate_region(mesh, settings, ext, &Region::whole(mesh.dimensions))
+}
+
+/// Distances of the first layer for pixels in the region only, in the same order as distances of
+/// the whole image
+pub fn generate_region(mesh: &Mesh, settings: &GenSettings, ext: &Extrema, region: &Region) -> Vec<Dist> {
+    // search without cancel token always finishes
+    generate_region_with(mesh, settings, ext, region, &Monitor::default()).unwrap_or_default()
+}
+
+/// Distances of the first layer for pixels in the region, reported to the monitor
+pub fn generate_region_with(mesh: &Mesh, settings: &GenSettings, ext: &Extrema, region: &Region, monitor: &Monitor) -> Result<Vec<Dist>, Cancelled> {
+    let spiral = generate_spiral(mesh.usable_radius);
+    let layer = layers(settings, ext)[0];
+    let mut layers = generate_layers_at(mesh, settings, &spiral, &[layer_search(&layer, mesh, settings)], region, monitor)?;
+    Ok(layers.swap_remove(0))
+}
+
+/// Distances for every layer in one pass, in the same order as `layers` returns them.
+/// All layers use the mesh of the largest radius with heights scaled by the radius of the layer,
+/// so every layer matches a run with only its radius.
+pub fn generate_layers(mesh: &Mesh, settings: &GenSettings, ext: &Extrema) -> Vec<Vec<Dist>> {
+    generate_layers_with(mesh, settings, ext, &Monitor::default()).unwrap_or_default()
+}
+
+/// Distances for every layer in one pass, reported to the monitor
+pub fn generate_layers_with(mesh: &Mesh, settings: &GenSettings, ext: &Extrema, monitor: &Monitor) -> Result<Vec<Vec<Dist>>, Cancelled> {
+    let spiral = generate_spiral(mesh.usable_radius);
+    let searches: Vec<(f32, isize, f32)> = layers(settings, ext).iter().map(|layer| layer_search(layer, mesh, settings)).collect();
+    generate_layers_at(mesh, settings, &spiral, &searches, &Region::whole(mesh.dimensions), monitor)
+}
+
+/// Signed distance to the heightfield on a 3D grid, one slice per pixel for `slices` heights
+/// evenly spread between the lowest and highest point of the image. Points under the surface
+/// are negative. Distances are searched only within the usable radius of the mesh.
+pub fn generate_volume(mesh: &Mesh, settings: &GenSettings, ext: &Extrema, slices: usize) -> Volume {
+    let spiral = generate_spiral(mesh.usable_radius);
+    let zero_index = zero_index(mesh, settings);
+    let row = mesh.ext_dim.0 as isize + 1;
+    let min = value_to_height(ext.min, settings.radius, settings);
+    let max = value_to_height(ext.max, settings.radius, settings);
+    let step = (max - min) / (slices.max(2) - 1) as f32;
+    let mut volume = Volume::new(
+        (mesh.dimensions.0, mesh.dimensions.1, slices),
+        Vec3::new((0.5, 0.5, min)),
+        Vec3::new((1.0, 1.0, step.max(f32::EPSILON))),
+    );
+    for z in 0..slices {
+        let height = min + z as f32 * step;
+        for dist in generate_distances_at(mesh, settings, &spiral, height) {
+            let surface = mesh.verts[(zero_index + dist.x + row * dist.y) as usize].z;
+            let index = volume.index(dist.x as usize, dist.y as usize, z);
+            volume.data[index] = if height < surface { -dist.dst } else { dist.dst };
+        }
+    }
+    volume
+}
+
+/// Capture height in mesh units, search reach and scale of mesh heights of the layer. Mesh heights
+/// are scaled from the largest radius to the radius of the layer.
+fn layer_search(layer: &Layer, mesh: &Mesh, settings: &GenSettings) -> (f32, isize, f32) {
+    (
+        value_to_height(layer.height, layer.radius, settings),
+        mesh.usable_radius_with(layer.radius, settings) as isize,
+        layer.radius as f32 / settings.radius as f32,
+    )
+}
+
+/// Convert pixel value to height in mesh units of given radius
+fn value_to_height(value: u8, radius: usize, settings: &GenSettings) -> f32 {
+    (value as f32 / 255.0) * radius as f32 * settings.img_height_mult
+}
+
+/// Offsets within given radius sorted by distance from the center
+fn generate_spiral(radius: usize) -> Vec<(isize, isize)> {
     let mut spiral: Vec<(isize, isize)> = Vec::new();
-    for y in -(mesh.usable_radius as isize)..=mesh.usable_radius as isize {
-        for x in -(mesh.usable_radius as isize)..=mesh.usable_radius as isize {
+    for y in -(radius as isize)..=radius as isize {
+        for x in -(radius as isize)..=radius as isize {
             spiral.push((x, y));
         }
     }
+    // points at the same distance are ordered by y and x, so the dropped point is the corner (radius, radius)
     spiral.sort_unstable_by(|a, b| {
         let a_sqr = (a.0 * a.0) + (a.1 * a.1);
         let b_sqr = (b.0 * b.0) + (b.1 * b.1);
@@ -27,182 +223,345 @@ pub fn generate_distances(mesh: &Mesh, settings: &GenSettings, ext: &Extrema) ->
         } else if b_sqr > a_sqr {
             Ordering::Less
         } else {
-            Ordering::Equal
+            (a.1, a.0).cmp(&(b.1, b.0))
         }
     });
     while spiral.len() % 8 != 0 {
         spiral.pop();
     }
-    println!("Spiral field done, {} points", spiral.len());
-    
-    let zero_index = match settings.repeat {
+    debug!("Spiral field done, {} points", spiral.len());
+    spiral
+}
+
+/// Index of the vertex over pixel (0, 0)
+fn zero_index(mesh: &Mesh, settings: &GenSettings) -> isize {
+    match settings.repeat {
         ImgRepeat::Repeat => ((mesh.ext_dim.0 + 1) * mesh.usable_radius + mesh.usable_radius) as isize,
         ImgRepeat::Clamp => (mesh.ext_dim.0 + 2) as isize,
+    }
+}
+
+/// Distances from every pixel at given capture height, searched along given spiral
+fn generate_distances_at(mesh: &Mesh, settings: &GenSettings, spiral: &[(isize, isize)], capture_height: f32) -> Vec<Dist> {
+    generate_layers_at(mesh, settings, spiral, &[(capture_height, isize::MAX, 1.0)], &Region::whole(mesh.dimensions), &Monitor::default())
+        .map(|mut layers| layers.swap_remove(0))
+        .unwrap_or_default()
+}
+
+/// Distances from pixels of the region for all layers given by capture height, reach and height scale,
+/// the spiral is walked once for all of them and offsets farther than the reach along any axis are skipped
+fn generate_layers_at(
+    mesh: &Mesh,
+    settings: &GenSettings,
+    spiral: &[(isize, isize)],
+    layers: &[(f32, isize, f32)],
+    region: &Region,
+    monitor: &Monitor,
+) -> Result<Vec<Vec<Dist>>, Cancelled> {
+    let search = Search {
+        mesh,
+        spiral,
+        layers,
+        zero_index: zero_index(mesh, settings),
+        clamp: matches!(settings.repeat, ImgRepeat::Clamp),
     };
-    
-    let get_distance = |x: isize, y: isize| {
-        let capture_point = Vec3::new((x as f32 + 0.5, y as f32 + 0.5, capture_height));
-        let mut dst = f32::MAX;
-        if let ImgRepeat::Clamp = settings.repeat {
-            for (x_sp, y_sp) in &spiral {
-                let x_act = x + *x_sp;
-                let y_act = y + *y_sp;
-                if x_act < 0 || x_act > mesh.dimensions.0 as isize || y_act < 0 || y_act > mesh.dimensions.1 as isize {
-                    continue;
-                }
-                if (x_act as f32 - capture_point.x).abs() > dst || (y_act as f32 - capture_point.y).abs() > dst {
-                    break;
-                }
-                let index = (zero_index + x_act + ((mesh.ext_dim.0 as isize + 1) * y_act)) as usize;
-                let point = &(mesh.verts[index]);
-                let dst_to_point = point.distance_to(&capture_point);
-                if dst_to_point < dst {
-                    dst = dst_to_point;
+    let skip_masked = settings.skip_masked && !mesh.mask.is_empty();
+
+    // generate chunks, mesh y goes up
+    let (x0, x1) = (region.x as isize, (region.x + region.width) as isize);
+    let (y0, y1) = ((mesh.dimensions.1 - region.y - region.height) as isize, (mesh.dimensions.1 - region.y) as isize);
+    let mut chunks: Vec<Vec<(isize, isize)>> = vec!();
+    for x in (x0..x1).step_by(64) {
+        for y in (y0..y1).step_by(64) {
+            let mut chunk = vec!();
+            for px in x..(x + 64).min(x1) {
+                for py in y..(y + 64).min(y1) {
+                    chunk.push((px, py))
                 }
             }
-        } else if let ImgRepeat::Repeat = settings.repeat {
-            for (x_sp, y_sp) in &spiral {
-                let x_act = x + *x_sp;
-                let y_act = y + *y_sp;
-                if (x_act as f32 - capture_point.x).abs() > dst || (y_act as f32 - capture_point.y).abs() > dst {
-                    break;
-                }
-                let index = (zero_index + x_act + ((mesh.ext_dim.0 as isize + 1) * y_act)) as usize;
-                let point = &(mesh.verts[index]);
-                let dst_to_point = point.distance_to(&capture_point);
-                if dst_to_point < dst {
-                    dst = dst_to_point;
+            chunks.push(chunk);
+        }
+    }
+
+    // generate distances, chunk results are split by layer
+    let done = AtomicUsize::new(0);
+    let cancelled = || monitor.cancel.as_ref().is_some_and(|token| token.is_cancelled());
+    let chunk_layers: Vec<Vec<Vec<Dist>>> = chunks.par_iter().enumerate().map(|(index, chunk)| {
+        let mut layers: Vec<Vec<Dist>> = search.layers.iter().map(|_| Vec::with_capacity(chunk.len())).collect();
+        if cancelled() {
+            return layers;
+        }
+        // values of every pixel for all layers, as they are checkpointed
+        let count = search.layers.len();
+        let stored = monitor
+            .checkpoint
+            .and_then(|checkpoint| checkpoint.get(index))
+            .filter(|values| values.len() == chunk.len() * count);
+        let mut values = Vec::with_capacity(chunk.len() * count);
+        let mut dsts = vec![0.0; count];
+        for (i, coords) in chunk.iter().enumerate() {
+            let masked = skip_masked && !search.pixel_used(coords.0, coords.1);
+            if let Some(stored) = stored {
+                dsts.copy_from_slice(&stored[i * count..(i + 1) * count]);
+            } else if masked {
+                for dst in dsts.iter_mut() {
+                    *dst = 0.0;
                 }
+            } else {
+                search.distances(coords.0, coords.1, &mut dsts);
             }
+            for (layer, dst) in layers.iter_mut().zip(dsts.iter()) {
+                layer.push(Dist{x: coords.0, y: coords.1, dst: *dst, masked});
+            }
+            values.extend_from_slice(&dsts);
         }
-        dst
-    };
+        if let (Some(checkpoint), None) = (monitor.checkpoint, stored) {
+            if let Err(error) = checkpoint.store(index, &values) {
+                warn!("Chunk {} not checkpointed: {}", index, error);
+            }
+        }
+        if let Some(progress) = monitor.progress {
+            progress((done.fetch_add(1, AtomicOrdering::Relaxed) + 1) as f32 / chunks.len() as f32);
+        }
+        layers
+    }).collect();
+    if cancelled() {
+        return Err(Cancelled);
+    }
 
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    #[target_feature(enable = "avx2")]
-    unsafe fn get_distance_avx2_repeat(x: isize, y: isize, capture_height: f32, spiral: &Vec<(isize, isize)>, mesh: &Mesh, zero_index: isize) -> f32 {
-        let capture_point = Vec3::new((x as f32 + 0.5, y as f32 + 0.5, capture_height));
-        let mut dst = f32::MAX;
-        for (x_sp, y_sp) in spiral {
-            let x_act = x + *x_sp;
-            let y_act = y + *y_sp;
-            if (x_act as f32 - capture_point.x).abs() > dst || (y_act as f32 - capture_point.y).abs() > dst {
-                break;
+    let mut layers: Vec<Vec<Dist>> = search.layers.iter().map(|_| vec!()).collect();
+    for chunk in chunk_layers {
+        for (layer, distances) in layers.iter_mut().zip(chunk) {
+            layer.extend(distances);
+        }
+    }
+
+    // sort distances
+    for distances in &mut layers {
+        distances.sort_by(|a,b| {
+            if a.y > b.y {
+                Ordering::Greater
+            } else if a.y == b.y {
+                if a.x > b.x {
+                    Ordering::Greater
+                } else if a.x == b.x {
+                    Ordering::Equal
+                } else {
+                    Ordering::Less
+                }
+            } else {
+                Ordering::Less
             }
-            let index = (zero_index + x_act + ((mesh.ext_dim.0 as isize + 1) * y_act)) as usize;
-            let point = &(mesh.verts[index]);
-            let dst_to_point = point.distance_to(&capture_point);
-            if dst_to_point < dst {
-                dst = dst_to_point;
+        });
+    }
+    Ok(layers)
+}
+
+/// Nearest vertex search around one pixel for several layers at once
+struct Search<'a> {
+    mesh: &'a Mesh,
+    spiral: &'a [(isize, isize)],
+    /// capture height, reach and scale of mesh heights of every layer
+    layers: &'a [(f32, isize, f32)],
+    zero_index: isize,
+    clamp: bool,
+}
+
+impl<'a> Search<'a> {
+    /// Whether mask allows the vertex over pixel (x, y), pixels outside of the image are used
+    fn pixel_used(&self, x: isize, y: isize) -> bool {
+        let mesh = self.mesh;
+        if x < 0 || y < 0 || x >= mesh.dimensions.0 as isize || y >= mesh.dimensions.1 as isize {
+            return true;
+        }
+        mesh.mask[(self.zero_index + x + ((mesh.ext_dim.0 as isize + 1) * y)) as usize]
+    }
+
+    /// Distances from pixel (x, y) for every layer
+    fn distances(&self, x: isize, y: isize, dsts: &mut [f32]) {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                unsafe { self.distances_avx2(x, y, dsts) };
+                return;
             }
         }
-        //println!("{:?}", dst);
-        dst
-    };
+        self.walk(x, y, dsts);
+    }
 
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     #[target_feature(enable = "avx2")]
-    unsafe fn get_distance_avx2_clamp(x: isize, y: isize, capture_height: f32, spiral: &Vec<(isize, isize)>, mesh: &Mesh, zero_index: isize) -> f32 {
-        let capture_point = Vec3::new((x as f32 + 0.5, y as f32 + 0.5, capture_height));
-        let mut dst = f32::MAX;
-        for (x_sp, y_sp) in spiral {
+    unsafe fn distances_avx2(&self, x: isize, y: isize, dsts: &mut [f32]) {
+        self.walk(x, y, dsts);
+    }
+
+    /// Walk the spiral until no closer vertex can be found for any of the layers
+    #[inline(always)]
+    fn walk(&self, x: isize, y: isize, dsts: &mut [f32]) {
+        let mesh = self.mesh;
+        let (capture_x, capture_y) = (x as f32 + 0.5, y as f32 + 0.5);
+        for dst in dsts.iter_mut() {
+            *dst = f32::MAX;
+        }
+        // largest of current distances, the search can stop beyond it
+        let mut bound = f32::MAX;
+        let masked = !mesh.mask.is_empty();
+        for (x_sp, y_sp) in self.spiral {
             let x_act = x + *x_sp;
             let y_act = y + *y_sp;
-            if x_act < 0 || x_act > mesh.dimensions.0 as isize || y_act < 0 || y_act > mesh.dimensions.1 as isize {
+            if self.clamp && (x_act < 0 || x_act > mesh.dimensions.0 as isize || y_act < 0 || y_act > mesh.dimensions.1 as isize) {
                 continue;
             }
-            if (x_act as f32 - capture_point.x).abs() > dst || (y_act as f32 - capture_point.y).abs() > dst {
+            // spiral goes outwards, vertices farther horizontally than every current distance cannot be closer
+            if ((x_sp * x_sp + y_sp * y_sp) as f32) > bound * bound {
                 break;
             }
-            let index = (zero_index + x_act + ((mesh.ext_dim.0 as isize + 1) * y_act)) as usize;
+            let index = (self.zero_index + x_act + ((mesh.ext_dim.0 as isize + 1) * y_act)) as usize;
+            if masked && !mesh.mask[index] {
+                continue;
+            }
             let point = &(mesh.verts[index]);
-            let dst_to_point = point.distance_to(&capture_point);
-            if dst_to_point < dst {
-                dst = dst_to_point;
+            let mut closer = false;
+            for ((height, reach, scale), dst) in self.layers.iter().zip(dsts.iter_mut()) {
+                // spiral of the reach alone would not have its corner (reach, reach)
+                if x_sp.abs() > *reach || y_sp.abs() > *reach || (*x_sp == *reach && *y_sp == *reach) {
+                    continue;
+                }
+                let dst_to_point = Vec3::new((point.x, point.y, point.z * scale)).distance_to(&Vec3::new((capture_x, capture_y, *height)));
+                if dst_to_point < *dst {
+                    *dst = dst_to_point;
+                    closer = true;
+                }
             }
-        }
-        dst
-    };
-
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    #[target_feature(enable = "avx2")]
-    let get_distance_avx2 = |x: isize, y: isize| {
-        match settings.repeat {
-            ImgRepeat::Clamp => {
-                unsafe { get_distance_avx2_clamp(x, y, capture_height, &spiral, &mesh, zero_index) }
-            },
-            ImgRepeat::Repeat => {
-                unsafe { get_distance_avx2_repeat(x, y, capture_height, &spiral, &mesh, zero_index) }
+            if closer {
+                bound = dsts.iter().cloned().fold(0.0, f32::max);
             }
         }
-    };
+    }
+}
 
-    // generate chunks
-    let mut chunks: Vec<Vec<(isize, isize)>> = vec!();
-    for x in 0..((mesh.dimensions.0 as f32 / 64.0).ceil() as isize) {
-        for y in 0..((mesh.dimensions.1 as f32 / 64.0).ceil() as isize) {
-            let mut chunk = vec!();
-            for px in (x * 64)..(x * 64 + 64) {
-                for py in (y * 64)..(y * 64 + 64) {
-                    chunk.push((px, py))
-                }
-            }
-            chunks.push(chunk);
-        }
+/// Grayscale image of distances remapped by output settings, masked pixels get mask fill value
+pub fn generate_image(dim: (usize, usize), distances: &[Dist], settings: &GenSettings) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let mut imgbuf = ImageBuffer::new(dim.0 as u32, dim.1 as u32);
+    let max = max_distance(distances);
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        let index = ((dim.1 - 1) - y as usize) * (dim.0) + x as usize;
+        let dist = &distances[index];
+        *pixel = if dist.masked {
+            Luma([settings.mask_fill])
+        } else {
+            // pixels without any unmasked vertex in reach are as far as possible
+            Luma([remap::distance_to_byte(&settings.output, dist.dst.min(max), max)])
+        };
     }
+    imgbuf
+}
 
-    // generate distances
-    let mut distances: Vec<Dist> = chunks.par_iter().map(|chunk| {
-        let mut distances: Vec<Dist> = vec!();
-        for coords in chunk {
-            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-            {
-                if is_x86_feature_detected!("avx2") {
-                    distances.push(Dist{x: coords.0, y: coords.1, dst: get_distance_avx2(coords.0, coords.1)});
-                    continue;
-                }
-            }
-            distances.push(Dist{x: coords.0, y: coords.1, dst: get_distance(coords.0, coords.1)});
-        }
-        distances
-    }).flatten().collect();
-    
-    // sort distances
-    distances.sort_by(|a,b| {
-        if a.y > b.y {
-            Ordering::Greater
-        } else if a.y == b.y {
-            if a.x > b.x {
-                Ordering::Greater
-            } else if a.x == b.x {
-                Ordering::Equal
-            } else {
-                Ordering::Less
-            }
+/// Write distances into the image at their pixels, normalized by clamp distance or by the largest of them
+pub fn patch_image(img: &mut GrayImage, distances: &[Dist], settings: &GenSettings) {
+    let max = settings.output.clamp_distance.unwrap_or_else(|| max_distance(distances));
+    let height = img.height();
+    for dist in distances {
+        let value = if dist.masked {
+            settings.mask_fill
         } else {
-            Ordering::Less
-        }
-    });
-    distances
+            remap::distance_to_byte(&settings.output, dist.dst.min(max), max)
+        };
+        img.put_pixel(dist.x as u32, height - 1 - dist.y as u32, Luma([value]));
+    }
 }
 
-pub fn generate_image(dim: (usize, usize), distances: &Vec<Dist>) -> ImageBuffer<Luma<u8>, Vec<u8>> {
-    let mut imgbuf = ImageBuffer::new(dim.0 as u32, dim.1 as u32);
-    let max = distances.par_iter().max_by(|x, y| {
-        if x.dst > y.dst {
-            return Ordering::Greater
+/// Pack up to four layers of distances into channels of one image, missing color channels
+/// are black and missing alpha is opaque
+pub fn generate_packed_image(dim: (usize, usize), layers: &[Vec<Dist>], settings: &GenSettings) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut imgbuf = ImageBuffer::from_pixel(dim.0 as u32, dim.1 as u32, Rgba([0, 0, 0, 255]));
+    for (channel, distances) in layers.iter().take(4).enumerate() {
+        let layer = generate_image(dim, distances, settings);
+        for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+            pixel.data[channel] = layer.get_pixel(x, y).data[0];
         }
-        Ordering::Less
-    }).unwrap().dst;
-    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-        let index = ((dim.1 - 1) - y as usize) as usize * (dim.0) + x as usize;
-        *pixel = Luma([255 - (distances[index].dst as f32 / max * 255.0) as u8]);
     }
     imgbuf
 }
 
+/// Largest of computed distances, used for normalization. Masked pixels and pixels where
+/// no vertex was found are left out.
+pub fn max_distance(distances: &[Dist]) -> f32 {
+    distances
+        .par_iter()
+        .filter(|dist| !dist.masked && dist.dst < f32::MAX)
+        .map(|dist| dist.dst)
+        .reduce(|| 0.0, f32::max)
+}
+
 pub struct Dist {
     pub x: isize,
     pub y: isize,
-    pub dst: f32
-}
\ No newline at end of file
+    pub dst: f32,
+    /// pixel was skipped by the mask, its distance is not valid
+    pub masked: bool,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageBuffer};
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(ImageBuffer::from_fn(40, 30, |x, y| Luma([((x * 37 + y * 91 + x * y) % 200 + 40) as u8])))
+    }
+
+    fn distances(img: &DynamicImage, settings: &GenSettings) -> Vec<Vec<f32>> {
+        let ext = Extrema::get_image_extrema(img);
+        let mesh = Mesh::generate(img, settings);
+        generate_layers(&mesh, settings, &ext)
+            .iter()
+            .map(|layer| layer.iter().map(|dist| dist.dst).collect())
+            .collect()
+    }
+
+    #[test]
+    fn every_radius_matches_run_with_only_that_radius() {
+        let img = image();
+        for repeat in [ImgRepeat::Repeat, ImgRepeat::Clamp] {
+            let mut settings = GenSettings::new_from_values((8, repeat, CaptureHeight::UserDefined(250), 1.0));
+            settings.radii = vec![3, 5, 8];
+            let layers = distances(&img, &settings);
+            for (radius, layer) in settings.radii.iter().zip(layers.iter()) {
+                let single = GenSettings::new_from_values((*radius, repeat, CaptureHeight::UserDefined(250), 1.0));
+                assert_eq!(&distances(&img, &single)[0], layer, "radius {} with {:?}", radius, repeat);
+            }
+        }
+    }
+
+    fn region(x: usize, y: usize, width: usize, height: usize) -> Region {
+        Region { x, y, width, height }
+    }
+
+    #[test]
+    fn region_parse() {
+        assert_eq!(Region::parse("1,2,3,4"), Some(region(1, 2, 3, 4)));
+        assert_eq!(Region::parse(" 1, 2 ,3,4"), Some(region(1, 2, 3, 4)));
+        assert_eq!(Region::parse("1,2,0,4"), None);
+        assert_eq!(Region::parse("1,2,3"), None);
+        assert_eq!(Region::parse("1,2,3,4,5"), None);
+        assert_eq!(Region::parse("-1,2,3,4"), None);
+    }
+
+    #[test]
+    fn region_clipped_to_image() {
+        assert_eq!(region(2, 3, 4, 5).clipped((10, 10)), region(2, 3, 4, 5));
+        assert_eq!(region(8, 7, 4, 5).clipped((10, 10)), region(8, 7, 2, 3));
+        assert_eq!(region(12, 3, 4, 5).clipped((10, 10)).width, 0);
+    }
+
+    #[test]
+    fn region_grown_wraps_only_repeated_images() {
+        let dim = (20, 10);
+        assert_eq!(region(5, 4, 3, 2).grown(2, dim, ImgRepeat::Clamp), region(3, 2, 7, 6));
+        assert_eq!(region(1, 4, 3, 2).grown(2, dim, ImgRepeat::Clamp), region(0, 2, 6, 6));
+        assert_eq!(region(5, 4, 3, 2).grown(2, dim, ImgRepeat::Repeat), region(3, 2, 7, 6));
+        // crosses the left edge, but not top or bottom
+        assert_eq!(region(1, 4, 3, 2).grown(2, dim, ImgRepeat::Repeat), region(0, 2, 20, 6));
+        assert_eq!(region(5, 7, 3, 2).grown(2, dim, ImgRepeat::Repeat), region(3, 0, 7, 10));
+    }
+}
@@ -0,0 +1,372 @@
+use image::{DynamicImage, ImageBuffer, Luma};
+use settings::MeshFormat;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::str::SplitWhitespace;
+use trimesh::TriMesh;
+use vec3::Vec3;
+
+/// Load triangle mesh from OBJ or PLY file, polygons are split into triangle fans.
+/// Meshes with infinite or NaN coordinates are rejected.
+pub fn load_mesh(filename: &str) -> io::Result<TriMesh> {
+    let file = BufReader::new(File::open(filename)?);
+    let mesh = match MeshFormat::from_filename(filename) {
+        Some(MeshFormat::Obj) => load_obj(file)?,
+        Some(MeshFormat::Ply) => load_ply(file)?,
+        _ => return Err(invalid("only OBJ and PLY meshes can be loaded")),
+    };
+    if mesh.verts.iter().any(|vert| !(vert.x.is_finite() && vert.y.is_finite() && vert.z.is_finite())) {
+        return Err(invalid("vertex coordinates have to be finite"));
+    }
+    Ok(mesh)
+}
+
+/// Check whether given file should be loaded as a mesh instead of an image
+pub fn is_mesh_file(filename: &str) -> bool {
+    matches!(MeshFormat::from_filename(filename), Some(MeshFormat::Obj) | Some(MeshFormat::Ply))
+}
+
+/// Rasterize mesh seen from above into a heightmap, Z is up. Longer side of the mesh gets
+/// `resolution` pixels, every pixel takes the highest point of the mesh over its whole area.
+/// Heights are stretched to the whole 0-255 range, pixels without mesh are black.
+pub fn heightmap_from_mesh(mesh: &TriMesh, resolution: u32) -> DynamicImage {
+    let (min, max) = mesh.bounds();
+    let extent = (max.x - min.x).max(max.y - min.y).max(f32::EPSILON);
+    let step = extent / resolution as f32;
+    let size = (
+        (((max.x - min.x) / step).round() as usize).max(1),
+        (((max.y - min.y) / step).round() as usize).max(1),
+    );
+    let heights = mesh.max_heights((min.x, min.y), (step, step), size);
+    let range = (max.z - min.z).max(f32::EPSILON);
+
+    let mut imgbuf = ImageBuffer::new(size.0 as u32, size.1 as u32);
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        // image rows go from top, mesh grid from bottom
+        let index = (size.1 - 1 - y as usize) * size.0 + x as usize;
+        let value = match heights[index] {
+            Some(height) => ((height - min.z) / range * 255.0).round() as u8,
+            None => 0,
+        };
+        *pixel = Luma([value]);
+    }
+    DynamicImage::ImageLuma8(imgbuf)
+}
+
+fn load_obj<R: BufRead>(reader: R) -> io::Result<TriMesh> {
+    let mut mesh = TriMesh::new();
+    for line in reader.lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let x = parse_next(&mut tokens)?;
+                let y = parse_next(&mut tokens)?;
+                let z = parse_next(&mut tokens)?;
+                mesh.verts.push(Vec3::new((x, y, z)));
+            }
+            Some("f") => {
+                let mut polygon = Vec::new();
+                for token in tokens {
+                    // only vertex index is needed from v/vt/vn
+                    let index = token.split('/').next().unwrap_or("");
+                    let index: i64 = index.parse().map_err(|_| invalid("invalid face index"))?;
+                    // negative indices count from the last vertex
+                    let index = if index < 0 { mesh.verts.len() as i64 + index } else { index - 1 };
+                    if index < 0 || index >= mesh.verts.len() as i64 {
+                        return Err(invalid("face index out of range"));
+                    }
+                    polygon.push(index as u32);
+                }
+                push_polygon(&mut mesh, &polygon);
+            }
+            _ => (),
+        }
+    }
+    Ok(mesh)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar(String, String),
+    List(String, String, String),
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+fn load_ply<R: BufRead>(mut reader: R) -> io::Result<TriMesh> {
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("missing end of PLY header"));
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", "ascii", ..] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", ..] => format = Some(PlyFormat::LittleEndian),
+            ["format", "binary_big_endian", ..] => format = Some(PlyFormat::BigEndian),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid("invalid element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => match elements.last_mut() {
+                Some(element) => element
+                    .properties
+                    .push(PlyProperty::List(count_type.to_string(), item_type.to_string(), name.to_string())),
+                None => return Err(invalid("property without element")),
+            },
+            ["property", value_type, name] => match elements.last_mut() {
+                Some(element) => element.properties.push(PlyProperty::Scalar(value_type.to_string(), name.to_string())),
+                None => return Err(invalid("property without element")),
+            },
+            ["end_header"] => break,
+            _ => (),
+        }
+    }
+    let format = format.ok_or_else(|| invalid("missing PLY format"))?;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut values = PlyValues::new(&data, format);
+
+    let mut mesh = TriMesh::new();
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut vert = (0.0, 0.0, 0.0);
+            for property in &element.properties {
+                match property {
+                    PlyProperty::Scalar(value_type, name) => {
+                        let value = values.next(value_type)? as f32;
+                        match name.as_str() {
+                            "x" => vert.0 = value,
+                            "y" => vert.1 = value,
+                            "z" => vert.2 = value,
+                            _ => (),
+                        }
+                    }
+                    PlyProperty::List(count_type, item_type, name) => {
+                        let count = values.next(count_type)? as usize;
+                        let mut polygon = Vec::with_capacity(count);
+                        for _ in 0..count {
+                            polygon.push(values.next(item_type)? as u32);
+                        }
+                        if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                            push_polygon(&mut mesh, &polygon);
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                mesh.verts.push(Vec3::new(vert));
+            }
+        }
+    }
+    if mesh.faces.iter().any(|face| face.iter().any(|index| *index as usize >= mesh.verts.len())) {
+        return Err(invalid("face index out of range"));
+    }
+    Ok(mesh)
+}
+
+/// Reader of PLY body values, converts everything to f64
+struct PlyValues<'a> {
+    data: &'a [u8],
+    format: PlyFormat,
+    position: usize,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> PlyValues<'a> {
+    fn new(data: &'a [u8], format: PlyFormat) -> PlyValues<'a> {
+        let text = match format {
+            PlyFormat::Ascii => ::std::str::from_utf8(data).unwrap_or(""),
+            _ => "",
+        };
+        PlyValues {
+            data,
+            format,
+            position: 0,
+            tokens: text.split_whitespace(),
+        }
+    }
+
+    fn next(&mut self, value_type: &str) -> io::Result<f64> {
+        if let PlyFormat::Ascii = self.format {
+            return self
+                .tokens
+                .next()
+                .and_then(|token| token.parse::<f64>().ok())
+                .ok_or_else(|| invalid("invalid PLY value"));
+        }
+        let size = match value_type {
+            "char" | "int8" | "uchar" | "uint8" => 1,
+            "short" | "int16" | "ushort" | "uint16" => 2,
+            "int" | "int32" | "uint" | "uint32" | "float" | "float32" => 4,
+            "double" | "float64" => 8,
+            _ => return Err(invalid("unknown PLY property type")),
+        };
+        if self.position + size > self.data.len() {
+            return Err(invalid("unexpected end of PLY data"));
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+        if let PlyFormat::BigEndian = self.format {
+            bytes[..size].reverse();
+        }
+        self.position += size;
+        let value = match value_type {
+            "char" | "int8" => f64::from(bytes[0] as i8),
+            "uchar" | "uint8" => f64::from(bytes[0]),
+            "short" | "int16" => f64::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+            "ushort" | "uint16" => f64::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+            "int" | "int32" => f64::from(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            "uint" | "uint32" => f64::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            "float" | "float32" => f64::from(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            _ => f64::from_le_bytes(bytes),
+        };
+        Ok(value)
+    }
+}
+
+/// Split polygon into a triangle fan
+fn push_polygon(mesh: &mut TriMesh, polygon: &[u32]) {
+    for i in 1..polygon.len().saturating_sub(1) {
+        mesh.faces.push([polygon[0], polygon[i], polygon[i + 1]]);
+    }
+}
+
+fn parse_next(tokens: &mut SplitWhitespace) -> io::Result<f32> {
+    tokens
+        .next()
+        .and_then(|token| token.parse::<f32>().ok())
+        .ok_or_else(|| invalid("invalid vertex coordinate"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use export;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn square() -> TriMesh {
+        let mut mesh = TriMesh::new();
+        mesh.verts = vec![
+            Vec3::new((0.0, 0.0, 0.5)),
+            Vec3::new((1.0, 0.0, 0.5)),
+            Vec3::new((1.0, 1.0, 1.5)),
+            Vec3::new((0.0, 1.0, 1.5)),
+        ];
+        mesh.faces = vec![[0, 1, 2], [0, 2, 3]];
+        mesh
+    }
+
+    #[test]
+    fn obj_round_trip() {
+        let mut mesh = square();
+        mesh.uvs = vec![(0.0, 0.0); 4];
+        mesh.compute_normals();
+        let mut bytes = Vec::new();
+        export::write_obj(&mesh, &mut bytes, None).unwrap();
+        let loaded = load_obj(&bytes[..]).unwrap();
+        assert_eq!(loaded.verts, mesh.verts);
+        assert_eq!(loaded.faces, mesh.faces);
+    }
+
+    #[test]
+    fn ply_round_trip() {
+        let mut mesh = square();
+        mesh.colors = vec![[0.2, 0.4, 0.6]; 4];
+        let mut bytes = Vec::new();
+        export::write_ply(&mesh, &mut bytes).unwrap();
+        let loaded = load_ply(&bytes[..]).unwrap();
+        assert_eq!(loaded.verts, mesh.verts);
+        assert_eq!(loaded.faces, mesh.faces);
+    }
+
+    #[test]
+    fn obj_polygons_are_fans_and_negative_indices_count_from_the_end() {
+        let text = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 1\nf 1/1 2/2 3/3 4/4\nf -4 -2 -1\n";
+        let mesh = load_obj(text.as_bytes()).unwrap();
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3], [0, 2, 3]]);
+        assert!(load_obj("v 0 0 0\nf 1 2 3\n".as_bytes()).is_err());
+        assert!(load_obj("v 0 0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn ply_ascii_and_big_endian() {
+        let header = "element vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+        let ascii = format!("ply\nformat ascii 1.0\n{}0 0 0\n1 0 0\n0 1 2\n3 0 1 2\n", header);
+        let mesh = load_ply(ascii.as_bytes()).unwrap();
+        assert_eq!(mesh.verts[2], Vec3::new((0.0, 1.0, 2.0)));
+        assert_eq!(mesh.faces, vec![[0, 1, 2]]);
+
+        let mut big = format!("ply\nformat binary_big_endian 1.0\n{}", header).into_bytes();
+        for value in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 2.0] {
+            big.extend_from_slice(&value.to_be_bytes());
+        }
+        big.push(3);
+        for index in &[0i32, 1, 2] {
+            big.extend_from_slice(&index.to_be_bytes());
+        }
+        let mesh = load_ply(&big[..]).unwrap();
+        assert_eq!(mesh.verts[2], Vec3::new((0.0, 1.0, 2.0)));
+        assert_eq!(mesh.faces, vec![[0, 1, 2]]);
+
+        assert!(load_ply(&big[..big.len() - 1]).is_err());
+        assert!(load_ply(ascii.replace("3 0 1 2", "3 0 1 5").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn meshes_with_nan_are_rejected() {
+        let filename = env::temp_dir().join(format!("distance_field_{}_nan.obj", process::id())).to_string_lossy().into_owned();
+        fs::write(&filename, "v 0 0 0\nv 1 0 NaN\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let result = load_mesh(&filename);
+        fs::remove_file(&filename).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn heightmap_keeps_ridge_narrower_than_a_pixel() {
+        // flat square 4 units wide with a thin ridge along x = 1.9
+        let mut mesh = TriMesh::new();
+        mesh.verts = vec![
+            Vec3::new((0.0, 0.0, 0.0)),
+            Vec3::new((4.0, 0.0, 0.0)),
+            Vec3::new((4.0, 4.0, 0.0)),
+            Vec3::new((0.0, 4.0, 0.0)),
+            Vec3::new((1.85, 0.0, 0.0)),
+            Vec3::new((1.9, 0.0, 1.0)),
+            Vec3::new((1.95, 0.0, 0.0)),
+            Vec3::new((1.85, 4.0, 0.0)),
+            Vec3::new((1.9, 4.0, 1.0)),
+            Vec3::new((1.95, 4.0, 0.0)),
+        ];
+        mesh.faces = vec![[0, 1, 2], [0, 2, 3], [4, 5, 8], [4, 8, 7], [5, 6, 9], [5, 9, 8]];
+        let img = heightmap_from_mesh(&mesh, 4).to_luma();
+        assert_eq!(img.dimensions(), (4, 4));
+        for y in 0..4 {
+            let row: Vec<u8> = (0..4).map(|x| img.get_pixel(x, y).data[0]).collect();
+            assert_eq!(row, vec![0, 255, 0, 0]);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use generator::{Dist, Region};
+use std::f32;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 4] = b"DFPT";
+
+/// One part of a run split across processes, rows of the image computed by one worker. The job
+/// file has one `name value` line for every field and one `arg` line for every argument.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub index: usize,
+    pub count: usize,
+    pub input: String,
+    /// dimensions of the whole image
+    pub dim: (usize, usize),
+    pub region: Region,
+    /// hash of the input and settings, workers and merging check it
+    pub key: u64,
+    /// file with distances of the region written by the worker
+    pub partial: String,
+    /// command line arguments the settings are parsed from, without program name
+    pub args: Vec<String>,
+}
+
+/// Rows of the image split evenly into `count` regions, there are no more regions than rows
+pub fn split_rows(dim: (usize, usize), count: usize) -> Vec<Region> {
+    let count = count.min(dim.1).max(1);
+    (0..count)
+        .map(|i| {
+            let (start, end) = (i * dim.1 / count, (i + 1) * dim.1 / count);
+            Region {
+                x: 0,
+                y: start,
+                width: dim.0,
+                height: end - start,
+            }
+        }).collect()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Job {
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        let region = &self.region;
+        let mut text = format!(
+            "job {} {}\ninput {}\ndimensions {} {}\nregion {},{},{},{}\nkey {:016x}\npartial {}\n",
+            self.index, self.count, self.input, self.dim.0, self.dim.1, region.x, region.y, region.width, region.height, self.key, self.partial
+        );
+        for arg in &self.args {
+            text.push_str(&format!("arg {}\n", arg));
+        }
+        fs::write(filename, text)
+    }
+
+    pub fn read(filename: &str) -> io::Result<Job> {
+        let text = fs::read_to_string(filename)?;
+        let mut fields: Vec<(&str, &str)> = Vec::new();
+        let mut args = Vec::new();
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            match line.split_once(' ') {
+                Some(("arg", value)) => args.push(String::from(value)),
+                Some(field) => fields.push(field),
+                None => return Err(invalid(format!("invalid line '{}' in {}", line, filename))),
+            }
+        }
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|field| field.0 == name)
+                .map(|field| field.1)
+                .ok_or_else(|| invalid(format!("missing {} in {}", name, filename)))
+        };
+        let pair = |name: &str| -> io::Result<(usize, usize)> {
+            let values: Vec<usize> = field(name)?.split(' ').map(|value| value.parse::<usize>()).collect::<Result<_, _>>().unwrap_or_default();
+            match values.as_slice() {
+                [a, b] => Ok((*a, *b)),
+                _ => Err(invalid(format!("invalid {} in {}", name, filename))),
+            }
+        };
+        let (index, count) = pair("job")?;
+        Ok(Job {
+            index,
+            count,
+            input: String::from(field("input")?),
+            dim: pair("dimensions")?,
+            region: Region::parse(field("region")?).ok_or_else(|| invalid(format!("invalid region in {}", filename)))?,
+            key: u64::from_str_radix(field("key")?, 16).map_err(|_| invalid(format!("invalid key in {}", filename)))?,
+            partial: String::from(field("partial")?),
+            args,
+        })
+    }
+
+    /// Write distances of the region to the partial file, masked pixels are stored as NaN
+    pub fn write_partial(&self, distances: &[Dist]) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.partial)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.key.to_le_bytes())?;
+        for value in &self.header() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for dist in distances {
+            let value = if dist.masked { f32::NAN } else { dist.dst };
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    fn header(&self) -> [u32; 6] {
+        let region = &self.region;
+        [self.index, self.count, region.x, region.y, region.width, region.height].map(|value| value as u32)
+    }
+}
+
+/// Distances of the whole image assembled from partial files. Jobs have to come from the same
+/// split, every one of them has to be there once, partial files have to match their jobs and
+/// together cover every pixel once.
+pub fn merge(jobs: &[Job]) -> io::Result<((usize, usize), Vec<Dist>)> {
+    let first = jobs.first().ok_or_else(|| invalid(String::from("no jobs to merge")))?;
+    let (dim, key, count) = (first.dim, first.key, first.count);
+    let mut present = vec![false; count];
+    for job in jobs {
+        if job.key != key || job.dim != dim || job.count != count {
+            return Err(invalid(format!("job {} is from another split", job.index)));
+        }
+        if job.index >= count || present[job.index] {
+            return Err(invalid(format!("job {} is given more than once or does not exist", job.index)));
+        }
+        present[job.index] = true;
+    }
+    if let Some(missing) = present.iter().position(|present| !present) {
+        return Err(invalid(format!("job {} of {} is missing", missing, count)));
+    }
+
+    let mut values: Vec<Option<f32>> = vec![None; dim.0 * dim.1];
+    for job in jobs {
+        let mut reader = BufReader::new(File::open(&job.partial)?);
+        let incomplete = |_| invalid(format!("partial {} is incomplete", job.partial));
+        let mut header = [0u8; 36];
+        reader.read_exact(&mut header).map_err(incomplete)?;
+        let mut expected = Vec::with_capacity(header.len());
+        expected.extend_from_slice(MAGIC);
+        expected.extend_from_slice(&key.to_le_bytes());
+        for value in &job.header() {
+            expected.extend_from_slice(&value.to_le_bytes());
+        }
+        if header[..] != expected[..] {
+            return Err(invalid(format!("partial {} does not belong to job {}", job.partial, job.index)));
+        }
+        let region = job.region.clipped(dim);
+        let mut bytes = vec![0u8; region.width * 4];
+        // distances go from the bottom row up
+        for y in (dim.1 - region.y - region.height)..(dim.1 - region.y) {
+            reader.read_exact(&mut bytes).map_err(incomplete)?;
+            for (x, b) in (region.x..).zip(bytes.chunks(4)) {
+                let value = &mut values[y * dim.0 + x];
+                if value.is_some() {
+                    return Err(invalid(format!("job {} overlaps another job", job.index)));
+                }
+                *value = Some(f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            }
+        }
+        if reader.read(&mut [0u8; 1])? != 0 {
+            return Err(invalid(format!("partial {} is longer than its region", job.partial)));
+        }
+    }
+
+    let mut distances = Vec::with_capacity(values.len());
+    for (index, value) in values.into_iter().enumerate() {
+        let dst = value.ok_or_else(|| invalid(String::from("some pixels are not covered by any job")))?;
+        distances.push(Dist {
+            x: (index % dim.0) as isize,
+            y: (index / dim.0) as isize,
+            dst: if dst.is_nan() { 0.0 } else { dst },
+            masked: dst.is_nan(),
+        });
+    }
+    Ok((dim, distances))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const DIM: (usize, usize) = (3, 4);
+
+    fn temp(name: &str) -> String {
+        env::temp_dir().join(format!("distance_field_{}_{}", process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    /// Jobs splitting the image in two, with partial files where every distance is its index
+    /// in the merged image
+    fn split(name: &str) -> Vec<Job> {
+        let regions = split_rows(DIM, 2);
+        let mut jobs = Vec::new();
+        for (index, region) in regions.into_iter().enumerate() {
+            let job = Job {
+                index,
+                count: 2,
+                input: String::from("input.png"),
+                dim: DIM,
+                region,
+                key: 0x1234,
+                partial: temp(&format!("{}_{}.part", name, index)),
+                args: vec![String::from("-r"), String::from("8")],
+            };
+            let rows = (DIM.1 - region.y - region.height)..(DIM.1 - region.y);
+            let distances: Vec<Dist> = rows
+                .flat_map(|y| (0..DIM.0).map(move |x| (x, y)))
+                .map(|(x, y)| Dist {
+                    x: x as isize,
+                    y: y as isize,
+                    dst: (y * DIM.0 + x) as f32,
+                    masked: x == 1 && y == 1,
+                }).collect();
+            job.write_partial(&distances).unwrap();
+            jobs.push(job);
+        }
+        jobs
+    }
+
+    fn remove(jobs: &[Job]) {
+        for job in jobs {
+            let _ = fs::remove_file(&job.partial);
+        }
+    }
+
+    fn merge_error(jobs: &[Job]) -> String {
+        match merge(jobs) {
+            Ok(_) => panic!("merge succeeded"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn split_rows_covers_every_row_once() {
+        let regions = split_rows((5, 7), 3);
+        assert_eq!(regions.iter().map(|region| (region.y, region.height)).collect::<Vec<_>>(), vec![(0, 2), (2, 2), (4, 3)]);
+        assert!(regions.iter().all(|region| region.x == 0 && region.width == 5));
+        assert_eq!(split_rows((5, 2), 4).len(), 2);
+    }
+
+    #[test]
+    fn job_file_round_trip() {
+        let jobs = split("job_file");
+        let job = &jobs[1];
+        let filename = temp("job_file.job");
+        job.write(&filename).unwrap();
+        let read = Job::read(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        remove(&jobs);
+        assert_eq!((read.index, read.count, read.dim, read.region, read.key), (1, 2, DIM, job.region, 0x1234));
+        assert_eq!((read.input, read.partial, read.args), (job.input.clone(), job.partial.clone(), job.args.clone()));
+    }
+
+    #[test]
+    fn merge_puts_every_distance_in_place() {
+        let mut jobs = split("merge");
+        jobs.reverse();
+        let (dim, distances) = merge(&jobs).unwrap();
+        remove(&jobs);
+        assert_eq!(dim, DIM);
+        for (index, dist) in distances.iter().enumerate() {
+            assert_eq!((dist.x, dist.y), ((index % DIM.0) as isize, (index / DIM.0) as isize));
+            assert_eq!(dist.masked, index == DIM.0 + 1);
+            if !dist.masked {
+                assert_eq!(dist.dst, index as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_rejects_wrong_sets_of_jobs() {
+        let jobs = split("sets");
+        assert!(merge_error(&[]).contains("no jobs"));
+        assert!(merge_error(&jobs[..1]).contains("job 1 of 2 is missing"));
+        assert!(merge_error(&[jobs[0].clone(), jobs[0].clone()]).contains("more than once"));
+        let mut other = jobs.clone();
+        other[1].key = 0x4321;
+        assert!(merge_error(&other).contains("another split"));
+        let mut other = jobs.clone();
+        other[1].dim = (3, 5);
+        assert!(merge_error(&other).contains("another split"));
+        remove(&jobs);
+    }
+
+    #[test]
+    fn merge_rejects_partials_not_matching_their_jobs() {
+        let jobs = split("partials");
+        let mut swapped = jobs.clone();
+        swapped[0].partial = jobs[1].partial.clone();
+        assert!(merge_error(&swapped).contains("does not belong"));
+
+        let bytes = fs::read(&jobs[1].partial).unwrap();
+        fs::write(&jobs[1].partial, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(merge_error(&jobs).contains("incomplete"));
+        let mut longer = bytes.clone();
+        longer.push(0);
+        fs::write(&jobs[1].partial, &longer).unwrap();
+        assert!(merge_error(&jobs).contains("longer than its region"));
+        remove(&jobs);
+    }
+
+    #[test]
+    fn merge_rejects_overlapping_and_missing_pixels() {
+        let jobs = split("coverage");
+        let region = jobs[0].region;
+        let rows = region.height * DIM.0;
+        let distances = |count: usize| -> Vec<Dist> {
+            (0..count)
+                .map(|_| Dist {
+                    x: 0,
+                    y: 0,
+                    dst: 1.0,
+                    masked: false,
+                }).collect()
+        };
+
+        let mut overlapping = jobs.clone();
+        overlapping[1].region = region;
+        overlapping[1].write_partial(&distances(rows)).unwrap();
+        assert!(merge_error(&overlapping).contains("overlaps"));
+
+        let mut short = jobs.clone();
+        short[1].region.height -= 1;
+        short[1].write_partial(&distances(short[1].region.height * DIM.0)).unwrap();
+        assert!(merge_error(&short).contains("not covered"));
+        remove(&jobs);
+    }
+}
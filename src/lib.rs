@@ -1,9 +1,28 @@
 extern crate clap;
+extern crate deflate;
 extern crate image;
+#[macro_use]
+extern crate log;
+extern crate png;
 extern crate rayon;
 
+pub mod cache;
+pub mod channel;
+pub mod checkpoint;
+pub mod colormap;
+pub mod export;
 pub mod extrema;
 pub mod generator;
+pub mod import;
+pub mod jobs;
 pub mod mesh;
+pub mod preprocess;
+pub mod remap;
+pub mod sdf;
 pub mod settings;
+pub mod simplify;
+pub mod stream;
+pub mod tiles;
+pub mod trimesh;
 pub mod vec3;
+pub mod volume;
@@ -2,73 +2,917 @@
 extern crate clap;
 extern crate distance_field;
 extern crate image;
+#[macro_use]
+extern crate log;
 
-use clap::App;
+use clap::{App, ArgMatches};
+use distance_field::cache::Cache;
+use distance_field::channel;
+use distance_field::channel::ChannelSource;
+use distance_field::checkpoint;
+use distance_field::checkpoint::{Checkpoint, Fnv};
 use distance_field::extrema::Extrema;
 use distance_field::generator;
+use distance_field::generator::{Dist, Monitor, Region};
+use distance_field::import;
+use distance_field::jobs;
+use distance_field::jobs::Job;
 use distance_field::mesh::Mesh;
+use distance_field::preprocess;
+use distance_field::remap;
+use distance_field::sdf;
 use distance_field::settings;
-use image::{GenericImage, ImageLuma8};
-use std::io;
+use distance_field::settings::{CaptureHeight, ExportRegion, ImgRepeat, MaskSource, MeshFormat};
+use distance_field::simplify;
+use distance_field::stream;
+use distance_field::tiles;
+use distance_field::tiles::TileGrid;
+use image::{DynamicImage, GenericImage, GrayImage, ImageError, ImageLuma8, ImageResult, ImageRgba8};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process;
+use std::sync::Mutex;
 use std::time::Instant;
 
+const BAR_WIDTH: usize = 40;
+
 fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
+    let level = if matches.is_present("quiet") {
+        LevelFilter::Warn
+    } else if matches.is_present("verbose") {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    let logger = CliLogger {
+        level,
+        json: matches.is_present("json"),
+    };
+    log::set_boxed_logger(Box::new(logger)).unwrap();
+    log::set_max_level(level);
     let mut whatever = String::from("");
-    let input = String::from(matches.value_of("INPUT").unwrap());
-    println!("Input filename is {}", &input);
-    let img = image::open(&input);
-    let img = match img {
+    if let Some(job) = matches.value_of("work") {
+        if let Err(error) = process_job(job) {
+            error!("Error with computing job {}: {}", job, error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    if matches.is_present("merge") {
+        let jobs: Vec<String> = matches.values_of("INPUT").unwrap().map(String::from).collect();
+        if let Err(error) = merge_jobs(&jobs) {
+            error!("Error with merging jobs: {}", error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(pattern) = matches.value_of("tiles") {
+        let settings = settings::GenSettings::new_from_input(&matches);
+        debug!("Settings: {:?}", settings);
+        if let Err(error) = process_tiles(pattern, &settings) {
+            error!("Error with processing tiles: {}", error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    let inputs: Vec<String> = matches.values_of("INPUT").unwrap().map(String::from).collect();
+    if inputs.len() > 1 {
+        let settings = settings::GenSettings::new_from_input(&matches);
+        debug!("Settings: {:?}", settings);
+        let ignored = batch_ignored(&matches, &settings);
+        if !ignored.is_empty() {
+            warn!("Only the first layer of every input is computed with more inputs, ignoring {}", ignored.join(", "));
+        }
+        if let Err(error) = process_batch(&inputs, &settings) {
+            error!("Error with processing batch: {}", error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    let input = inputs[0].clone();
+    info!("Input filename is {}", &input);
+    let mut settings = settings::GenSettings::new_from_input(&matches);
+    settings.export.texture = Some(get_output_filename(&input));
+    debug!("Settings: {:?}", settings);
+    if let (true, Some(resolution)) = (import::is_mesh_file(&input), settings.volume_resolution) {
+        if let Err(error) = compute_mesh_volume(&input, resolution) {
+            error!("Error with computing volume of {}: {}", &input, error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(band_rows) = settings.stream_rows {
+        if settings.mask.is_some() || settings.preprocess.blur.is_some() || settings.preprocess.median.is_some() {
+            warn!("Masks, blur and median filter are not supported with streaming, ignoring them");
+        }
+        if settings.radii.len() > 1 || matches!(settings.height_setting, CaptureHeight::Multiple(ref heights) if heights.len() > 1) {
+            warn!("Only the first radius and capture height are computed with streaming, ignoring the rest");
+        }
+        let now = Instant::now();
+        if let Err(error) = stream::process(&input, &get_output_filename(&input), &settings, band_rows) {
+            error!("Error with streaming {}: {}", &input, error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        log_time("Image streamed", now);
+        return;
+    }
+    let img = match load_input(&input, &settings) {
         Ok(file) => file,
         Err(error) => {
-            eprintln!("Error with opening file {} :, {:?}", &input, error);
+            error!("Error with opening file {} :, {:?}", &input, error);
             io::stdin().read_line(&mut whatever).unwrap();
             process::exit(1);
         }
     };
-    println!("Image dimensions are {:?}", img.dimensions());
-    let settings = settings::GenSettings::new_from_input(&matches);
-    println!("Settings: {:?}", settings);
-    let now = Instant::now();
-    let mesh = Mesh::generate(&img, &settings);
-    let time = now.elapsed();
-    println!("Mesh generated in {}", time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9);
-    println!("Verts: {:?}", mesh.verts.iter().count());
+    info!("Image dimensions are {:?}", img.dimensions());
+    if let Some(count) = settings.split {
+        if let Err(error) = split_jobs(&input, img.dimensions(), &matches, &settings, count) {
+            error!("Error with splitting {} into jobs: {}", &input, error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    let mask = match load_mask(&img, &settings) {
+        Ok(mask) => mask,
+        Err(error) => {
+            error!("Error with opening mask: {}", error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+    };
+    let cache = settings.cache_dir.as_ref().map(|dir| Cache::new(dir, &img, mask.as_ref(), &settings));
+    if settings.per_channel {
+        if let Err(error) = process_channels(&input, &img, mask.as_ref(), &settings) {
+            error!("Error with saving file: {}", error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    let img = prepare_image(img, &settings);
     let ext = Extrema::get_image_extrema(&img);
+    let layer_list = generator::layers(&settings, &ext);
+    let outputs = get_output_filenames(&input, &layer_list, &settings);
+    if let Some(ref cache) = cache {
+        match cache.restore(&outputs) {
+            Ok(true) => {
+                info!(cache = "hit", key = format!("{:016x}", cache.key()); "Cache hit, outputs of previous run reused");
+                if packs_layers(&layer_list, &settings) {
+                    if let Err(error) = relink_sidecar(&input) {
+                        error!("Error with updating restored sidecar: {}", error);
+                        io::stdin().read_line(&mut whatever).unwrap();
+                        process::exit(1);
+                    }
+                }
+                return;
+            }
+            Ok(false) => info!(cache = "miss", key = format!("{:016x}", cache.key()); "Cache miss, computing outputs"),
+            Err(error) => warn!("Cached outputs not restored: {}", error),
+        }
+    }
     let now = Instant::now();
-    let distances = generator::generate_distances(&mesh, &settings, &ext);
-    let time = now.elapsed();
-    println!("Distances computed in {}", time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9);
-    if let 1 = matches.occurrences_of("export") {
+    let mut mesh = Mesh::generate(&img, &settings);
+    if let Some(ref mask) = mask {
+        mesh.apply_mask(mask, settings.repeat);
+    }
+    log_time("Mesh generated", now);
+    info!("Verts: {:?}", mesh.verts.len());
+    if settings.region.is_some() || settings.dirty.is_some() {
+        if let Err(error) = process_region(&input, &mesh, &settings, &ext) {
+            error!("Error with computing region of {}: {}", &input, error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(slices) = settings.volume_resolution {
+        if let Err(error) = compute_heightfield_volume(&input, &mesh, &settings, &ext, slices) {
+            error!("Error with saving volume of {}: {}", &input, error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        return;
+    }
+    let now = Instant::now();
+    // checkpoint hashes the input and writes every chunk, so it is only kept when asked for
+    let checkpoint = if matches.is_present("checkpoint") || matches.is_present("resume") {
+        match open_checkpoint(&input, mask.as_ref(), &settings, matches.is_present("resume")) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(error) => {
+                warn!("Checkpoint not available: {}", error);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let bar = ProgressBar::new();
+    let progress = |fraction: f32| bar.update(fraction);
+    let monitor = Monitor {
+        progress: Some(&progress),
+        cancel: None,
+        checkpoint: checkpoint.as_ref(),
+    };
+    let layers = generator::generate_layers_with(&mesh, &settings, &ext, &monitor).unwrap_or_default();
+    let distances = &layers[0];
+    log_time("Distances computed", now);
+    if let (true, Some(max_error)) = (matches.is_present("compare"), settings.export.simplify) {
         let now = Instant::now();
-        mesh.export("output.obj", &settings);
-        let time = now.elapsed();
-        println!("Mesh exported in {}", time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9);
+        let simplified = simplify::simplify(&mesh, &mesh.grid_rect(ExportRegion::Padded), max_error);
+        info!("Simplified mesh has {} verts and {} faces", simplified.verts.len(), simplified.faces.len());
+        let simplified_distances = generator::generate_distances(&mesh.resampled(&simplified), &settings, &ext);
+        let (max_diff, sum_diff) = distances
+            .iter()
+            .zip(simplified_distances.iter())
+            .map(|(full, simple)| (full.dst - simple.dst).abs())
+            .fold((0.0f32, 0.0f64), |(max, sum), diff| (max.max(diff), sum + f64::from(diff)));
+        info!(
+            "Distance difference to simplified mesh: max {}, mean {}",
+            max_diff,
+            sum_diff / distances.len() as f64
+        );
+        log_time("Comparison done", now);
+    }
+    if matches.occurrences_of("export") == 1 || matches.is_present("mesh_output") {
+        let filename = match matches.value_of("mesh_output") {
+            Some(filename) => String::from(filename),
+            None => format!("output.{}", settings.export.format.unwrap_or(MeshFormat::Obj).extension()),
+        };
+        let now = Instant::now();
+        if let Err(error) = mesh.export(&filename, &settings, Some(distances)) {
+            error!("Error with exporting mesh {}: {}", &filename, error);
+            io::stdin().read_line(&mut whatever).unwrap();
+            process::exit(1);
+        }
+        log_time("Mesh exported", now);
     }
     // separate image into buffers
     // compute buffer
     // save image
-    let out_img = generator::generate_image(mesh.dimensions, &distances);
-    match ImageLuma8(out_img).save(get_output_filename(&input)) {
+    let result = if packs_layers(&layer_list, &settings) {
+        if layer_list.len() > 4 {
+            warn!("Only first four layers can be packed");
+        }
+        save_packed_image(&input, &layer_list, &layers, &mesh, &settings)
+    } else if layer_list.len() > 1 {
+        layer_list.iter().zip(layers.iter()).try_for_each(|(layer, distances)| {
+            let out_img = generator::generate_image(mesh.dimensions, distances, &settings);
+            ImageLuma8(out_img).save(get_height_filename(&input, layer.height))
+        })
+    } else {
+        ImageLuma8(generator::generate_image(mesh.dimensions, distances, &settings)).save(get_output_filename(&input))
+    };
+    match result {
         Ok(_) => {
-            println!("Image saved successfully");
+            info!("Image saved successfully");
+            // checkpoint is kept until outputs are saved, so a run stopped while saving can resume
+            if let Some(checkpoint) = checkpoint {
+                if let Err(error) = checkpoint.remove() {
+                    warn!("Checkpoint not removed: {}", error);
+                }
+            }
+            if let Some(cache) = cache {
+                if let Err(error) = cache.store(&outputs) {
+                    warn!("Outputs not cached: {}", error);
+                }
+            }
         }
         Err(error) => {
-            eprintln!("Error with saving file: {}", error);
+            error!("Error with saving file: {}", error);
             io::stdin().read_line(&mut whatever).unwrap();
             process::exit(1);
         }
     };
 }
 
+/// Progress bar with estimated remaining time, logged under `progress` target
+struct ProgressBar {
+    start: Instant,
+    /// last logged percentage
+    percent: Mutex<usize>,
+}
+
+impl ProgressBar {
+    fn new() -> ProgressBar {
+        ProgressBar {
+            start: Instant::now(),
+            percent: Mutex::new(0),
+        }
+    }
+
+    /// Log the bar when the percentage changes
+    fn update(&self, fraction: f32) {
+        let percent = (fraction * 100.0) as usize;
+        let mut last = self.percent.lock().unwrap();
+        if percent <= *last {
+            return;
+        }
+        *last = percent;
+        let time = self.start.elapsed();
+        let elapsed = time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9;
+        let remaining = elapsed * f64::from(1.0 - fraction) / f64::from(fraction);
+        let filled = percent * BAR_WIDTH / 100;
+        info!(
+            target: "progress",
+            fraction = f64::from(fraction), remaining = remaining;
+            "[{}{}] {:3}% ETA {:.1}s", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled), percent, remaining
+        );
+    }
+}
+
+/// Log how long a stage took, JSON lines get the stage and seconds as fields
+fn log_time(stage: &str, now: Instant) {
+    let time = now.elapsed();
+    let seconds = time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9;
+    info!(stage = stage, seconds = seconds; "{} in {}", stage, seconds);
+}
+
+/// Logger of the command line, plain messages or one JSON object per line
+struct CliLogger {
+    level: LevelFilter,
+    json: bool,
+}
+
+impl Log for CliLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if self.json {
+            let mut line = format!(
+                "{{\"level\": \"{}\", \"target\": \"{}\", \"message\": {}",
+                record.level(),
+                record.target(),
+                json_string(&record.args().to_string())
+            );
+            record.key_values().visit(&mut JsonFields(&mut line)).unwrap_or(());
+            line.push('}');
+            println!("{}", line);
+        } else if record.target() == "progress" {
+            // bar is redrawn on the same line until it is full
+            print!("\r{}", record.args());
+            if record.key_values().get(Key::from("fraction")).and_then(|value| value.to_f64()) >= Some(1.0) {
+                println!();
+            }
+            io::stdout().flush().unwrap();
+        } else if record.level() <= Level::Warn {
+            eprintln!("{}", record.args());
+        } else {
+            println!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {
+        io::stdout().flush().unwrap();
+    }
+}
+
+/// Appends key values of a record as JSON fields, numbers stay numbers
+struct JsonFields<'a>(&'a mut String);
+
+impl<'a, 'kvs> VisitSource<'kvs> for JsonFields<'a> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = match value.to_f64() {
+            Some(number) if number.is_finite() => number.to_string(),
+            _ => json_string(&value.to_string()),
+        };
+        self.0.push_str(&format!(", {}: {}", json_string(key.as_str()), value));
+        Ok(())
+    }
+}
+
+/// Quoted JSON string
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Distances of the first layer with progress bar
+fn generate_distances(mesh: &Mesh, settings: &settings::GenSettings, ext: &Extrema) -> Vec<Dist> {
+    generate_region(mesh, settings, ext, &Region::whole(mesh.dimensions))
+}
+
+/// Distances of the first layer for pixels in the region with progress bar
+fn generate_region(mesh: &Mesh, settings: &settings::GenSettings, ext: &Extrema, region: &Region) -> Vec<Dist> {
+    let bar = ProgressBar::new();
+    let progress = |fraction: f32| bar.update(fraction);
+    let monitor = Monitor {
+        progress: Some(&progress),
+        cancel: None,
+        checkpoint: None,
+    };
+    generator::generate_region_with(mesh, settings, ext, region, &monitor).unwrap_or_default()
+}
+
+/// Checkpoint of distances of the input, keyed by hash of the input, its mask and settings
+fn open_checkpoint(input: &str, mask: Option<&GrayImage>, settings: &settings::GenSettings, resume: bool) -> io::Result<Checkpoint> {
+    let mut hasher = Fnv::default();
+    hasher.write_u64(checkpoint::hash_file(input, &format!("{:?}", settings))?);
+    if let Some(mask) = mask {
+        hasher.write(mask);
+    }
+    let key = hasher.finish();
+    let checkpoint = Checkpoint::open(&get_suffixed_filename(input, "output", "checkpoint"), key, resume)?;
+    if checkpoint.resumed() > 0 {
+        info!("Resuming with {} finished chunks", checkpoint.resumed());
+    }
+    Ok(checkpoint)
+}
+
+/// Open input image, meshes are rasterized into heightmap
+fn load_input(input: &str, settings: &settings::GenSettings) -> ImageResult<DynamicImage> {
+    if import::is_mesh_file(input) {
+        load_mesh_heightmap(input, settings)
+    } else {
+        image::open(input)
+    }
+}
+
+/// Select channel of the input and preprocess it
+fn prepare_image(img: DynamicImage, settings: &settings::GenSettings) -> DynamicImage {
+    if settings.preprocess.is_active() {
+        let gray = channel::extract(&img, settings.channel.unwrap_or(ChannelSource::Red)).to_luma();
+        ImageLuma8(preprocess::preprocess(&gray, &settings.preprocess))
+    } else {
+        match settings.channel {
+            Some(source) => channel::extract(&img, source),
+            None => img,
+        }
+    }
+}
+
+/// Options which are not supported when more inputs are normalized together
+fn batch_ignored(matches: &ArgMatches, settings: &settings::GenSettings) -> Vec<&'static str> {
+    let mut ignored = Vec::new();
+    if !settings.radii.is_empty() {
+        ignored.push("radius list");
+    }
+    if let CaptureHeight::Multiple(_) = settings.height_setting {
+        ignored.push("height list");
+    }
+    if settings.pack {
+        ignored.push("packing");
+    }
+    if matches.is_present("export") || matches.is_present("mesh_output") {
+        ignored.push("mesh export");
+    }
+    if settings.per_channel {
+        ignored.push("per channel mode");
+    }
+    if settings.volume_resolution.is_some() {
+        ignored.push("volume");
+    }
+    if settings.stream_rows.is_some() {
+        ignored.push("streaming");
+    }
+    if settings.region.is_some() || settings.dirty.is_some() {
+        ignored.push("region");
+    }
+    if settings.split.is_some() {
+        ignored.push("split");
+    }
+    if settings.cache_dir.is_some() {
+        ignored.push("cache");
+    }
+    if matches.is_present("checkpoint") || matches.is_present("resume") {
+        ignored.push("checkpoint");
+    }
+    ignored
+}
+
+/// Compute distances of all inputs first, then save all of them normalized by their largest
+/// distance, or by clamp distance when set, so neighbouring tiles or frames match
+fn process_batch(inputs: &[String], settings: &settings::GenSettings) -> ImageResult<()> {
+    let mut results = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        info!("Input filename is {}", input);
+        let img = load_input(input, settings)?;
+        let mask = load_mask(&img, settings)?;
+        let img = prepare_image(img, settings);
+        let now = Instant::now();
+        let mut mesh = Mesh::generate(&img, settings);
+        if let Some(ref mask) = mask {
+            mesh.apply_mask(mask, settings.repeat);
+        }
+        let ext = Extrema::get_image_extrema(&img);
+        let distances = generate_distances(&mesh, settings, &ext);
+        log_time("Distances computed", now);
+        results.push((input.clone(), mesh.dimensions, distances));
+    }
+    save_normalized(&results, settings)?;
+    Ok(())
+}
+
+/// Compute grid of tiles, padding of every tile is filled from its neighbours and all tiles share
+/// capture height and normalization, so outputs are seamless
+fn process_tiles(pattern: &str, settings: &settings::GenSettings) -> ImageResult<()> {
+    let (columns, rows) = tiles::grid_size(pattern);
+    if columns == 0 {
+        let error = io::Error::new(io::ErrorKind::NotFound, format!("no tile {}", tiles::tile_filename(pattern, 0, 0)));
+        return Err(ImageError::IoError(error));
+    }
+    info!("Tile grid is {}x{}", columns, rows);
+    if settings.mask.is_some() {
+        warn!("Masks are not supported with tiles, ignoring mask");
+    }
+    if settings.preprocess.blur.is_some() || settings.preprocess.median.is_some() {
+        warn!("Blur and median filter are applied to every tile separately");
+    }
+    let mut images = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let img = load_input(&tiles::tile_filename(pattern, column, row), settings)?;
+            images.push(prepare_image(img, settings).to_luma());
+        }
+    }
+    let grid = TileGrid::new(columns, rows, images)?;
+    let ext = grid.extrema();
+    // tile meshes have padding laid out like repeated images
+    let mut settings = settings.clone();
+    settings.repeat = ImgRepeat::Repeat;
+    let settings = &settings;
+
+    let mut results = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let now = Instant::now();
+            let mesh = grid.mesh(column, row, &ext, settings);
+            let distances = generate_distances(&mesh, settings, &ext);
+            log_time(&format!("Tile {}, {} computed", column, row), now);
+            results.push((tiles::tile_filename(pattern, column, row), mesh.dimensions, distances));
+        }
+    }
+    save_normalized(&results, settings)?;
+    Ok(())
+}
+
+/// Save output images normalized by the largest distance of all of them, or by clamp distance when set
+fn save_normalized(results: &[(String, (usize, usize), Vec<Dist>)], settings: &settings::GenSettings) -> io::Result<()> {
+    let max = match settings.output.clamp_distance {
+        Some(max) => max,
+        None => results.iter().map(|(_, _, distances)| generator::max_distance(distances)).fold(0.0, f32::max),
+    };
+    let mut settings = settings.clone();
+    settings.output.clamp_distance = Some(max);
+    info!("Shared maximal distance is {}", max);
+    for (input, dim, distances) in results {
+        ImageLuma8(generator::generate_image(*dim, distances, &settings)).save(get_output_filename(input))?;
+    }
+    info!("Images saved successfully");
+    Ok(())
+}
+
+/// Load mask selected in settings, it has to have the same size as the image
+fn load_mask(img: &DynamicImage, settings: &settings::GenSettings) -> ImageResult<Option<GrayImage>> {
+    let mask = match settings.mask {
+        Some(MaskSource::Alpha) => channel::extract(img, ChannelSource::Alpha).to_luma(),
+        Some(MaskSource::Image(ref filename)) => image::open(filename)?.to_luma(),
+        None => return Ok(None),
+    };
+    if mask.dimensions() != img.dimensions() {
+        return Err(ImageError::DimensionError);
+    }
+    Ok(Some(mask))
+}
+
+/// Compute every channel of the image independently with its own settings and save them
+/// as channels of one image
+fn process_channels(input: &str, img: &DynamicImage, mask: Option<&GrayImage>, settings: &settings::GenSettings) -> io::Result<()> {
+    let mut outputs = Vec::new();
+    for (index, source) in channel::image_channels(img).into_iter().enumerate() {
+        let settings = settings.for_channel(index);
+        let mut channel_img = channel::extract(img, source);
+        if settings.preprocess.is_active() {
+            channel_img = ImageLuma8(preprocess::preprocess(&channel_img.to_luma(), &settings.preprocess));
+        }
+        let now = Instant::now();
+        let mut mesh = Mesh::generate(&channel_img, &settings);
+        if let Some(mask) = mask {
+            mesh.apply_mask(mask, settings.repeat);
+        }
+        let ext = Extrema::get_image_extrema(&channel_img);
+        let distances = generate_distances(&mesh, &settings, &ext);
+        log_time(&format!("Channel {:?} computed", source), now);
+        outputs.push(generator::generate_image(mesh.dimensions, &distances, &settings));
+    }
+    channel::combine(&outputs).save(get_output_filename(input))?;
+    info!("Image saved successfully");
+    Ok(())
+}
+
+/// Compute only pixels of the region, or patch previous output around the dirty region
+fn process_region(input: &str, mesh: &Mesh, settings: &settings::GenSettings, ext: &Extrema) -> ImageResult<()> {
+    let output = get_output_filename(input);
+    let now = Instant::now();
+    // values are scaled by the largest distance, which only the whole image has, clamp distance
+    // makes them match the same pixels of the whole image
+    if settings.output.clamp_distance.is_none() {
+        let message = match settings.dirty {
+            Some(_) => "patching needs clamp distance of the previous output",
+            None => "region needs clamp distance to match the whole image",
+        };
+        return Err(ImageError::IoError(io::Error::new(io::ErrorKind::InvalidInput, message)));
+    }
+    match (settings.region, settings.dirty) {
+        (_, Some(dirty)) => {
+            if let CaptureHeight::Generated = settings.height_setting {
+                warn!("Capture height is generated from the edited image, it has to match the previous output");
+            }
+            let mut img = image::open(settings.previous.as_ref().unwrap_or(&output))?.to_luma();
+            if img.dimensions() != (mesh.dimensions.0 as u32, mesh.dimensions.1 as u32) {
+                return Err(ImageError::DimensionError);
+            }
+            // vertices under the edit are nearest to pixels at most usable radius away
+            let region = dirty.clipped(mesh.dimensions).grown(mesh.usable_radius + 1, mesh.dimensions, settings.repeat);
+            let distances = generator::generate_region(mesh, settings, ext, &region);
+            generator::patch_image(&mut img, &distances, settings);
+            log_time(&format!("Region {:?} recomputed", region), now);
+            ImageLuma8(img).save(&output)?;
+        }
+        (Some(region), None) => {
+            let region = region.clipped(mesh.dimensions);
+            if region.width == 0 || region.height == 0 {
+                return Err(ImageError::DimensionError);
+            }
+            let distances = generator::generate_region(mesh, settings, ext, &region);
+            log_time(&format!("Region {:?} computed", region), now);
+            ImageLuma8(generator::generate_image((region.width, region.height), &distances, settings)).save(&output)?;
+        }
+        (None, None) => (),
+    }
+    info!("Image saved successfully");
+    Ok(())
+}
+
+/// Write job files for computing rows of the input in separate processes. Jobs get arguments
+/// of this run, with values entered interactively added so workers do not ask for them.
+fn split_jobs(input: &str, dim: (u32, u32), matches: &ArgMatches, settings: &settings::GenSettings, count: usize) -> io::Result<()> {
+    if !settings.radii.is_empty() || matches!(settings.height_setting, CaptureHeight::Multiple(_)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "jobs compute only one radius and capture height"));
+    }
+    let mut args = Vec::new();
+    let mut split_value = false;
+    for arg in std::env::args().skip(1) {
+        if split_value {
+            split_value = false;
+        } else if arg == "--split" {
+            split_value = true;
+        } else if !arg.starts_with("--split=") {
+            args.push(arg);
+        }
+    }
+    args.extend(prompted_args(matches, settings));
+    let dim = (dim.0 as usize, dim.1 as usize);
+    let mut job = Job {
+        index: 0,
+        count,
+        input: String::from(input),
+        dim,
+        region: Region::whole(dim),
+        key: 0,
+        partial: String::new(),
+        args,
+    };
+    job.key = checkpoint::hash_file(input, &format!("{:?}", job_settings(&job)?))?;
+    let regions = jobs::split_rows(dim, count);
+    for (index, region) in regions.iter().enumerate() {
+        let job = Job {
+            index,
+            count: regions.len(),
+            region: *region,
+            partial: get_suffixed_filename(input, &format!("part{}", index), "dfpart"),
+            ..job.clone()
+        };
+        let filename = get_suffixed_filename(input, &format!("job{}", index), "txt");
+        job.write(&filename)?;
+        info!("Job of rows {} to {} written to {}", region.y, region.y + region.height, filename);
+    }
+    Ok(())
+}
+
+/// Arguments of settings which were entered interactively
+fn prompted_args(matches: &ArgMatches, settings: &settings::GenSettings) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    if !matches.is_present("radius") {
+        args.extend(vec![String::from("-r"), settings.radius.to_string()]);
+    }
+    if !matches.is_present("clamp") && !matches.is_present("repeat") {
+        args.push(String::from(match settings.repeat {
+            ImgRepeat::Clamp => "-c",
+            ImgRepeat::Repeat => "-p",
+        }));
+    }
+    if !matches.is_present("height") && !matches.is_present("gen") {
+        match settings.height_setting {
+            CaptureHeight::Generated => args.push(String::from("-g")),
+            CaptureHeight::UserDefined(value) => args.extend(vec![String::from("-h"), value.to_string()]),
+            CaptureHeight::Multiple(ref values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                args.extend(vec![String::from("-h"), values.join(",")]);
+            }
+        }
+    }
+    if !matches.is_present("mult") {
+        args.extend(vec![String::from("-m"), settings.img_height_mult.to_string()]);
+    }
+    args
+}
+
+/// Settings of the job parsed from its arguments
+fn job_settings(job: &Job) -> io::Result<settings::GenSettings> {
+    let yaml = load_yaml!("cli.yml");
+    let args = std::iter::once(String::from("distance_field")).chain(job.args.iter().cloned());
+    let matches = App::from_yaml(yaml)
+        .get_matches_from_safe(args)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.message))?;
+    Ok(settings::GenSettings::new_from_input(&matches))
+}
+
+/// Compute rows of the job and write them to its partial file
+fn process_job(filename: &str) -> ImageResult<()> {
+    let job = Job::read(filename)?;
+    info!("Job {} of {}, input filename is {}", job.index, job.count, job.input);
+    let settings = job_settings(&job)?;
+    if checkpoint::hash_file(&job.input, &format!("{:?}", settings))? != job.key {
+        let error = io::Error::new(io::ErrorKind::InvalidData, "input or settings changed since the split");
+        return Err(ImageError::IoError(error));
+    }
+    let img = load_input(&job.input, &settings)?;
+    let mask = load_mask(&img, &settings)?;
+    let img = prepare_image(img, &settings);
+    let now = Instant::now();
+    let mut mesh = Mesh::generate(&img, &settings);
+    if let Some(ref mask) = mask {
+        mesh.apply_mask(mask, settings.repeat);
+    }
+    let ext = Extrema::get_image_extrema(&img);
+    let distances = generate_region(&mesh, &settings, &ext, &job.region);
+    log_time(&format!("Rows {} to {} computed", job.region.y, job.region.y + job.region.height), now);
+    job.write_partial(&distances)?;
+    info!("Partial result saved to {}", job.partial);
+    Ok(())
+}
+
+/// Assemble partial results of all jobs of a split into the output image
+fn merge_jobs(filenames: &[String]) -> ImageResult<()> {
+    let jobs = filenames.iter().map(|filename| Job::read(filename)).collect::<io::Result<Vec<Job>>>()?;
+    let (dim, distances) = jobs::merge(&jobs)?;
+    let settings = job_settings(&jobs[0])?;
+    let output = get_output_filename(&jobs[0].input);
+    ImageLuma8(generator::generate_image(dim, &distances, &settings)).save(&output)?;
+    info!("{} jobs merged into {}", jobs.len(), output);
+    Ok(())
+}
+
+/// Compute signed distance volume of the mesh and save it next to the mesh
+fn compute_mesh_volume(input: &str, resolution: usize) -> ImageResult<()> {
+    let mesh = import::load_mesh(input)?;
+    info!("Mesh loaded, {} verts, {} faces", mesh.verts.len(), mesh.faces.len());
+    let now = Instant::now();
+    let volume = sdf::mesh_sdf(&mesh, resolution);
+    log_time(&format!("Volume {:?} computed", volume.dims), now);
+    let header = volume.write_raw(&get_suffixed_filename(input, "sdf", "raw"))?;
+    info!("Volume saved, header is {}", header);
+    let prefix = get_suffixed_filename(input, "sdf", "png");
+    volume.write_slices(&prefix[..prefix.len() - 4])?;
+    info!("Slices saved successfully");
+    Ok(())
+}
+
+/// Compute signed distance volume of the heightfield and save it next to the input image
+fn compute_heightfield_volume(input: &str, mesh: &Mesh, settings: &settings::GenSettings, ext: &Extrema, slices: usize) -> ImageResult<()> {
+    let now = Instant::now();
+    let volume = generator::generate_volume(mesh, settings, ext, slices);
+    log_time(&format!("Volume {:?} computed", volume.dims), now);
+    let header = volume.write_raw(&get_suffixed_filename(input, "sdf", "raw"))?;
+    info!("Volume saved, header is {}", header);
+    let prefix = get_suffixed_filename(input, "sdf", "png");
+    volume.write_slices(&prefix[..prefix.len() - 4])?;
+    info!("Slices saved successfully");
+    Ok(())
+}
+
+/// Rasterize mesh into heightmap
+fn load_mesh_heightmap(input: &str, settings: &settings::GenSettings) -> ImageResult<DynamicImage> {
+    let now = Instant::now();
+    let mesh = import::load_mesh(input)?;
+    info!("Mesh loaded, {} verts, {} faces", mesh.verts.len(), mesh.faces.len());
+    let img = import::heightmap_from_mesh(&mesh, settings.input_resolution);
+    log_time("Heightmap rasterized", now);
+    Ok(img)
+}
+
 fn get_output_filename(input: &str) -> String {
     let path = PathBuf::from(input);
     let extension = path.extension().unwrap().to_str().unwrap();
+    // meshes produce images
+    let extension = if import::is_mesh_file(input) { "png" } else { extension };
+    get_suffixed_filename(input, "output", extension)
+}
+
+/// Save up to four layers packed into channels of RGBA image, with a JSON sidecar
+/// recording radius, capture height and normalization of every channel
+fn save_packed_image(
+    input: &str,
+    layer_list: &[generator::Layer],
+    layers: &[Vec<Dist>],
+    mesh: &Mesh,
+    settings: &settings::GenSettings,
+) -> io::Result<()> {
+    let filename = get_suffixed_filename(input, "output", "png");
+    ImageRgba8(generator::generate_packed_image(mesh.dimensions, layers, settings)).save(&filename)?;
+
+    let mut json = format!("{{\n  \"image\": {},\n  \"channels\": [\n", json_string(&packed_image_name(input)));
+    let count = layer_list.len().min(4);
+    for (i, (layer, distances)) in layer_list.iter().zip(layers.iter()).take(count).enumerate() {
+        json.push_str(&format!(
+            "    {{\"channel\": \"{}\", \"radius\": {}, \"capture_height\": {}, \"max_distance\": {}}}{}\n",
+            ["R", "G", "B", "A"][i],
+            layer.radius,
+            layer.height,
+            remap::normalization_distance(&settings.output, generator::max_distance(distances)),
+            if i + 1 < count { "," } else { "" }
+        ));
+    }
+    json.push_str("  ]\n}\n");
+    fs::write(get_suffixed_filename(input, "output", "json"), json)?;
+    Ok(())
+}
+
+/// More layers are packed into one image when asked for or when there are more radii
+fn packs_layers(layer_list: &[generator::Layer], settings: &settings::GenSettings) -> bool {
+    layer_list.len() > 1 && (settings.pack || !settings.radii.is_empty())
+}
+
+/// Files written for the layers, packed image with its sidecar, image of every layer or one image
+fn get_output_filenames(input: &str, layer_list: &[generator::Layer], settings: &settings::GenSettings) -> Vec<String> {
+    if packs_layers(layer_list, settings) {
+        vec![get_suffixed_filename(input, "output", "png"), get_suffixed_filename(input, "output", "json")]
+    } else if layer_list.len() > 1 {
+        layer_list.iter().map(|layer| get_height_filename(input, layer.height)).collect()
+    } else {
+        vec![get_output_filename(input)]
+    }
+}
+
+/// File name of packed image, as the sidecar refers to it
+fn packed_image_name(input: &str) -> String {
+    let filename = get_suffixed_filename(input, "output", "png");
+    PathBuf::from(&filename).file_name().unwrap().to_str().unwrap().to_string()
+}
+
+/// Point sidecar restored from cache to the packed image of this input, the cached one may
+/// come from an input with another name
+fn relink_sidecar(input: &str) -> io::Result<()> {
+    let filename = get_suffixed_filename(input, "output", "json");
+    let image = format!("  \"image\": {},", json_string(&packed_image_name(input)));
+    let json: Vec<String> = fs::read_to_string(&filename)?
+        .lines()
+        .map(|line| if line.starts_with("  \"image\": ") { image.clone() } else { String::from(line) })
+        .collect();
+    fs::write(&filename, json.join("\n") + "\n")
+}
+
+/// Output image for one of multiple capture heights
+fn get_height_filename(input: &str, height: u8) -> String {
+    let output = get_output_filename(input);
+    let index = output.rfind('.').unwrap();
+    format!("{}_{}{}", &output[..index], height, &output[index..])
+}
+
+fn get_suffixed_filename(input: &str, suffix: &str, extension: &str) -> String {
+    let path = PathBuf::from(input);
+    let input_extension = path.extension().unwrap().to_str().unwrap();
     let file_name = String::from(path.file_name().unwrap().to_str().unwrap());
-    let index = file_name.rfind(&extension).unwrap();
-    let file_name = format!("{}_output.{}", &file_name[0..index - 1], &extension);
+    let index = file_name.rfind(&input_extension).unwrap();
+    let file_name = format!("{}_{}.{}", &file_name[0..index - 1], suffix, extension);
     String::from(path.with_file_name(&file_name).to_str().unwrap())
 }
@@ -1,10 +1,16 @@
+use colormap::Colormap;
+use export;
 use extrema::Extrema;
-use image::{DynamicImage, GenericImage, Pixel};
-use settings::{CaptureHeight, GenSettings, ImgRepeat};
+use generator::{self, Dist};
+use image::{DynamicImage, GenericImage, GrayImage, Pixel};
+use settings::{ExportRegion, GenSettings, ImgRepeat, MeshFormat, UpAxis};
+use remap;
+use simplify;
 use std::f32;
-use std::fs::File;
-use std::io::Write;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
+use trimesh::TriMesh;
 use vec3::Vec3;
 
 const EXPORT_SCALE: f32 = 1.0 / 100.0;
@@ -16,12 +22,25 @@ macro_rules! new_vert {
     };
 }
 
+/// Part of the vertex grid, bounds are inclusive vertex indices
+#[derive(Debug, Clone, Copy)]
+pub struct GridRect {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
 #[derive(Debug)]
 pub struct Mesh {
     pub dimensions: (usize, usize),
     pub ext_dim: (usize, usize),
     pub usable_radius: usize,
+    /// capture height above the lowest value the usable radius was computed for
+    pub height_range: u8,
     pub verts: Vec<Arc<Vec3>>,
+    /// vertices excluded from distance search are false, empty when all of them are used
+    pub mask: Vec<bool>,
 }
 
 impl Mesh {
@@ -42,65 +61,259 @@ impl Mesh {
             _ => (middle.dimensions.0 - 1 + 2 * middle.usable_radius, middle.dimensions.1 - 1 + 2 * middle.usable_radius),
         };
         middle.ext_dim = bounds;
-        println!("Points generated");
+        debug!("Points generated");
         middle
     }
 
-    /// Export mesh data in obj. format.
-    pub fn export(&self, filename: &str, settings: &GenSettings) {
-        let mut file = File::create(filename).unwrap();
-        let mut data = String::from("");
-        for point in &self.verts {
-            let p = point;
-            data.push_str(&format!("v {} {} {}\n", p.x * EXPORT_SCALE, p.y * EXPORT_SCALE, p.z * EXPORT_SCALE));
+    /// Export mesh data, format is taken from settings or from the file extension.
+    /// With level of detail export enabled, every level is written with `_lodN` suffix.
+    /// Distances are needed only for vertex colors.
+    pub fn export(&self, filename: &str, settings: &GenSettings, distances: Option<&[Dist]>) -> io::Result<()> {
+        match settings.export.lod_levels {
+            Some(levels) => {
+                for level in 0..levels {
+                    // first level keeps detail of the normal export, error doubles with every next level
+                    let max_error = match level {
+                        0 => settings.export.simplify,
+                        _ => Some(settings.export.lod_error * 2.0f32.powi(level as i32 - 1)),
+                    };
+                    let skirt = (2.0 * max_error.unwrap_or(0.0)).max(1.0);
+                    self.export_level(&lod_filename(filename, level), settings, distances, max_error, Some(skirt))?;
+                }
+                Ok(())
+            }
+            None => self.export_level(filename, settings, distances, settings.export.simplify, None),
         }
-        println!("All points written.");
-        let points_in_row = match settings.repeat {
-            ImgRepeat::Clamp => (self.dimensions.0 + 2) as usize,
-            _ => self.ext_dim.0 + 1,
+    }
+
+    fn export_level(
+        &self,
+        filename: &str,
+        settings: &GenSettings,
+        distances: Option<&[Dist]>,
+        max_error: Option<f32>,
+        skirt: Option<f32>,
+    ) -> io::Result<()> {
+        let format = settings
+            .export
+            .format
+            .or_else(|| MeshFormat::from_filename(filename))
+            .unwrap_or(MeshFormat::Obj);
+        let rect = self.grid_rect(settings.export.region);
+        let mut mesh = match max_error {
+            Some(max_error) => simplify::simplify(self, &rect, max_error),
+            None => self.to_trimesh(&rect),
         };
-        println!("{:?}", points_in_row);
-        for i in 0..(self.verts.iter().count() * 2) {
-            let row = (i / 2) / (points_in_row - 1);
-            let pos_in_row = i - row * (points_in_row - 1) * 2;
-            match i % 2 {
-                0 => {
-                    data.push_str(&format!(
-                        "f {} {} {}\n",
-                        pos_in_row / 2 + (row + 1) * (points_in_row) + 2,
-                        pos_in_row / 2 + (row + 1) * (points_in_row) + 1,
-                        pos_in_row / 2 + row * (points_in_row) + 1,
-                    ));
-                }
-                1 => {
-                    data.push_str(&format!(
-                        "f {} {} {}\n",
-                        pos_in_row / 2 + (row + 1) * (points_in_row) + 2,
-                        pos_in_row / 2 + row * (points_in_row) + 1,
-                        pos_in_row / 2 + row * (points_in_row) + 2
-                    ));
+        if let (Some(colormap), Some(distances)) = (settings.export.colormap, distances) {
+            mesh.colors = self.vertex_colors(&mesh.verts, distances, settings, colormap);
+        }
+        if let (Some(depth), false) = (skirt, settings.export.solid) {
+            mesh.add_skirt(depth);
+        }
+        let scale = match (settings.export.print_size, settings.export.scale) {
+            (Some(size), _) => {
+                let (min, max) = mesh.bounds();
+                size / (max.x - min.x).max(max.y - min.y)
+            }
+            (None, Some(scale)) => scale,
+            (None, None) => EXPORT_SCALE,
+        };
+        mesh.scale(scale);
+        if settings.export.center {
+            let (min, max) = mesh.bounds();
+            mesh.translate((-(min.x + max.x) / 2.0, -(min.y + max.y) / 2.0, 0.0));
+        }
+        if settings.export.solid {
+            mesh.solidify(settings.export.base_depth);
+        }
+        if let UpAxis::Y = settings.export.up_axis {
+            mesh.to_y_up();
+        }
+        match (format, &settings.export.texture) {
+            (MeshFormat::Obj, Some(texture)) => {
+                mesh.compute_normals();
+                export::write_obj_textured(&mesh, filename, texture)
+            }
+            _ => export::write_mesh(&mesh, filename, format),
+        }
+    }
+
+    /// Number of vertices in one row of the vertex grid and number of rows.
+    pub fn grid_size(&self) -> (usize, usize) {
+        let row = self.ext_dim.0 + 1;
+        (row, self.verts.len() / row)
+    }
+
+    /// Part of the vertex grid to export. Core region covers the image area and one more row and column
+    /// of vertices, so meshes of neighbouring tiles share their border.
+    pub fn grid_rect(&self, region: ExportRegion) -> GridRect {
+        let (row, rows) = self.grid_size();
+        match region {
+            ExportRegion::Padded => GridRect {
+                x0: 0,
+                y0: 0,
+                x1: row - 1,
+                y1: rows - 1,
+            },
+            ExportRegion::Core => {
+                // first vertex lies at 0.5 in image space
+                let x0 = (0.5 - self.verts[0].x).round() as usize;
+                let y0 = (0.5 - self.verts[0].y).round() as usize;
+                GridRect {
+                    x0,
+                    y0,
+                    x1: (x0 + self.dimensions.0).min(row - 1),
+                    y1: (y0 + self.dimensions.1).min(rows - 1),
                 }
-                _ => (),
-            };
+            }
+        }
+    }
+
+    /// Triangulate given part of the vertex grid, two faces per grid cell.
+    /// Uvs map the central tile to 0-1.
+    pub fn to_trimesh(&self, rect: &GridRect) -> TriMesh {
+        let (row, _) = self.grid_size();
+        let width = rect.x1 - rect.x0 + 1;
+        let mut verts: Vec<Vec3> = Vec::with_capacity(width * (rect.y1 - rect.y0 + 1));
+        for y in rect.y0..=rect.y1 {
+            for x in rect.x0..=rect.x1 {
+                verts.push(self.verts[y * row + x].as_ref().clone());
+            }
+        }
+        let mut faces = Vec::with_capacity((width - 1) * (rect.y1 - rect.y0) * 2);
+        for y in 0..(rect.y1 - rect.y0) {
+            for x in 0..(width - 1) {
+                let bottom = (y * width + x) as u32;
+                let top = ((y + 1) * width + x) as u32;
+                faces.push([top + 1, top, bottom]);
+                faces.push([top + 1, bottom, bottom + 1]);
+            }
+        }
+        TriMesh {
+            uvs: self.texture_uvs(&verts),
+            verts,
+            normals: Vec::new(),
+            colors: Vec::new(),
+            faces,
         }
-        println!("All faces written.");
-        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    /// Texture coordinates of given points, central tile maps to 0-1.
+    pub fn texture_uvs(&self, verts: &[Vec3]) -> Vec<(f32, f32)> {
+        verts
+            .iter()
+            .map(|vert| (vert.x / self.dimensions.0 as f32, vert.y / self.dimensions.1 as f32))
+            .collect()
+    }
+
+    /// Colors of given points from distances of the pixels under them. Points outside of the image
+    /// take the pixel they repeat or are clamped to. Distances are remapped like in the output image.
+    pub fn vertex_colors(&self, verts: &[Vec3], distances: &[Dist], settings: &GenSettings, colormap: Colormap) -> Vec<[f32; 3]> {
+        let max = generator::max_distance(distances);
+        let (w, h) = (self.dimensions.0 as isize, self.dimensions.1 as isize);
+        verts
+            .iter()
+            .map(|vert| {
+                let (x, y) = (vert.x.floor() as isize, vert.y.floor() as isize);
+                let (x, y) = match settings.repeat {
+                    ImgRepeat::Repeat => (x.rem_euclid(w), y.rem_euclid(h)),
+                    ImgRepeat::Clamp => (x.max(0).min(w - 1), y.max(0).min(h - 1)),
+                };
+                let dst = distances[(y * w + x) as usize].dst.min(max);
+                colormap.map(remap::distance_to_value(&settings.output, dst, max))
+            }).collect()
+    }
+
+    /// Copy of the mesh with heights taken from given surface, e.g. a simplified version of this mesh.
+    /// Vertices not covered by the surface keep their height.
+    pub fn resampled(&self, surface: &TriMesh) -> Mesh {
+        let (row, rows) = self.grid_size();
+        let origin = (self.verts[0].x, self.verts[0].y);
+        let heights = surface.sample_heights(origin, (1.0, 1.0), (row, rows));
+        let verts = self
+            .verts
+            .iter()
+            .zip(heights.iter())
+            .map(|(vert, height)| new_vert!(vert.x, vert.y, height.unwrap_or(vert.z)))
+            .collect();
+        Mesh {
+            dimensions: self.dimensions,
+            ext_dim: self.ext_dim,
+            verts,
+            usable_radius: self.usable_radius,
+            height_range: self.height_range,
+            mask: self.mask.clone(),
+        }
+    }
+
+    /// Exclude vertices over pixels where mask is zero from distance search, mask has
+    /// to have the same size as the image the mesh was generated from
+    pub fn apply_mask(&mut self, mask: &GrayImage, repeat: ImgRepeat) {
+        let dim = self.dimensions;
+        self.mask = self
+            .verts
+            .iter()
+            .map(|vert| {
+                let coords = match repeat {
+                    ImgRepeat::Repeat => Mesh::mesh_to_image_coords_repeat((vert.x, vert.y), dim),
+                    ImgRepeat::Clamp => Mesh::mesh_to_image_coords_clamped((vert.x, vert.y), dim),
+                };
+                mask.get_pixel(coords.0, coords.1).data[0] > 0
+            }).collect();
+    }
+
+    /// Generate mesh of a tile, padding around it is filled by `sample`, which returns the image value
+    /// of the pixel at given mesh coordinates, also outside of the tile. Extrema have to cover all values
+    /// the sampler returns, they limit the padding.
+    pub fn generate_sampled<F>(dim: (usize, usize), ext: &Extrema, settings: &GenSettings, sample: F) -> Mesh
+    where
+        F: Fn(isize, isize) -> u8,
+    {
+        let mut mesh = Mesh::generate_padded(dim, ext, settings, sample);
+        mesh.ext_dim = (mesh.dimensions.0 - 1 + 2 * mesh.usable_radius, mesh.dimensions.1 - 1 + 2 * mesh.usable_radius);
+        debug!("Points generated");
+        mesh
     }
 
     /// Generate mesh data from given image.
     fn generate_mesh(img: &DynamicImage, settings: &GenSettings) -> Mesh {
         let dim = img.dimensions();
         let dim = (dim.0 as usize, dim.1 as usize);
+        // nearest vertex of the lowest pixel can be as far as the whole height range, not only at the border
+        let ext = Extrema::get_image_extrema(img);
+        Mesh::generate_padded(dim, &ext, settings, |x, y| {
+            let coords = Mesh::mesh_to_image_coords_repeat((x as f32 + 0.5, y as f32 + 0.5), dim);
+            img.get_pixel(coords.0, coords.1).channels()[0]
+        })
+    }
 
-        // get maximal usable radius
-        let ext = Extrema::get_border_extrema(&img);
-        let height = match settings.height_setting {
-            CaptureHeight::Generated => ext.max,
-            CaptureHeight::UserDefined(val) => val,
-        };
-        let max_radius = (settings.radius as f32 * (f32::from(height - ext.min) / 255.0) * settings.img_height_mult) as usize;
-        let max_radius = max_radius.min(settings.radius);
+    /// Largest horizontal distance of a vertex which can be the nearest one, padding needed around
+    /// an image with given extrema
+    pub fn max_radius(ext: &Extrema, settings: &GenSettings) -> usize {
+        Mesh::radius_in_range(settings.radius, Mesh::height_range(ext, settings), settings)
+    }
+
+    /// Usable radius of a mesh generated with given radius instead, heights of its vertices are scaled
+    /// by the radius, so its padding is scaled too
+    pub fn usable_radius_with(&self, radius: usize, settings: &GenSettings) -> usize {
+        Mesh::radius_in_range(radius, self.height_range, settings).min((self.dimensions.0).min(self.dimensions.1))
+    }
+
+    fn height_range(ext: &Extrema, settings: &GenSettings) -> u8 {
+        settings.height_setting.max_value(ext.max) - ext.min
+    }
+
+    fn radius_in_range(radius: usize, height_range: u8, settings: &GenSettings) -> usize {
+        let max_radius = (radius as f32 * (f32::from(height_range) / 255.0) * settings.img_height_mult) as usize;
+        max_radius.min(radius)
+    }
 
+    /// Generate mesh padded by usable radius on every side, heights are taken from the sampler
+    fn generate_padded<F>(dim: (usize, usize), ext: &Extrema, settings: &GenSettings, sample: F) -> Mesh
+    where
+        F: Fn(isize, isize) -> u8,
+    {
+        let max_radius = Mesh::max_radius(ext, settings);
         let vec_size = (dim.0 + max_radius) * (dim.1 + max_radius);
         let mut verts: Vec<Arc<Vec3>> = Vec::with_capacity(vec_size);
 
@@ -113,11 +326,10 @@ impl Mesh {
         );
         for y in y_low..y_high {
             for x in x_low..x_high {
-                let coords = Mesh::mesh_to_image_coords_repeat((x as f32 + 0.5, y as f32 + 0.5), dim);
                 verts.push(new_vert!(
                     x as f32 + 0.5,
                     y as f32 + 0.5,
-                    Mesh::compute_height(img.get_pixel(coords.0, coords.1).channels()[0], &settings)
+                    Mesh::compute_height(sample(x as isize, y as isize), settings)
                 ));
             }
         }
@@ -126,6 +338,8 @@ impl Mesh {
             ext_dim: (dim.0 + 2 * max_radius, dim.1 + 2 * max_radius),
             verts,
             usable_radius: max_radius.min((dim.0).min(dim.1)),
+            height_range: Mesh::height_range(ext, settings),
+            mask: Vec::new(),
         }
     }
 
@@ -133,14 +347,9 @@ impl Mesh {
         let dim = img.dimensions();
         let dim = (dim.0 as usize, dim.1 as usize);
 
-        // get maximal usable radius
-        let ext = Extrema::get_border_extrema(&img);
-        let height = match settings.height_setting {
-            CaptureHeight::Generated => ext.max,
-            CaptureHeight::UserDefined(val) => val,
-        };
-        let max_radius = (settings.radius as f32 * (f32::from(height - ext.min) / 255.0) * settings.img_height_mult) as usize;
-        let max_radius = max_radius.min(settings.radius);
+        // get maximal usable radius, from extrema of the whole image like repeated meshes
+        let ext = Extrema::get_image_extrema(img);
+        let max_radius = Mesh::max_radius(&ext, settings);
 
         let mut verts: Vec<Arc<Vec3>> = Vec::with_capacity((dim.0 + 2) * (dim.1 + 2));
         for y in -1..=(dim.1 as isize) {
@@ -149,7 +358,7 @@ impl Mesh {
                 verts.push(new_vert!(
                     x as f32 + 0.5,
                     y as f32 + 0.5,
-                    Mesh::compute_height(img.get_pixel(img_coords.0, img_coords.1).channels()[0], &settings)
+                    Mesh::compute_height(img.get_pixel(img_coords.0, img_coords.1).channels()[0], settings)
                 ));
             }
         }
@@ -158,6 +367,8 @@ impl Mesh {
             ext_dim: (dim.0 + 2, dim.1 + 2),
             verts,
             usable_radius: max_radius.min((dim.0).min(dim.1)),
+            height_range: Mesh::height_range(&ext, settings),
+            mask: Vec::new(),
         }
     }
 
@@ -188,7 +399,7 @@ impl Mesh {
 
     /// Compute mesh height from given image value
     fn compute_height(pix: u8, settings: &GenSettings) -> f32 {
-        ((f32::from(pix) / 255.0) * (settings.radius as f32) * (settings.img_height_mult))
+        (f32::from(pix) / 255.0) * (settings.radius as f32) * (settings.img_height_mult)
     }
 }
 
@@ -203,10 +414,23 @@ impl Clone for Mesh {
             ext_dim: self.ext_dim,
             verts,
             usable_radius: self.usable_radius,
+            height_range: self.height_range,
+            mask: self.mask.clone(),
         }
     }
 }
 
+/// Insert level of detail suffix before file extension
+fn lod_filename(filename: &str, level: usize) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_lod{}.{}", stem, level, ext.to_string_lossy()),
+        None => format!("{}_lod{}", stem, level),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
 pub fn clamp_to_range(val: f32, min: f32, max: f32) -> f32 {
     val.min(max).max(min)
 }
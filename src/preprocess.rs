@@ -0,0 +1,102 @@
+use image::{imageops, GrayImage, ImageBuffer, Luma};
+use rayon::prelude::*;
+use settings::PreprocessSettings;
+
+/// Apply all preprocessing from settings to grayscale image
+pub fn preprocess(img: &GrayImage, settings: &PreprocessSettings) -> GrayImage {
+    let mut img = match settings.median {
+        Some(radius) => median(img, radius),
+        None => img.clone(),
+    };
+    if let Some(sigma) = settings.blur {
+        img = imageops::blur(&img, sigma);
+    }
+    let lut = lookup_table(settings);
+    for pixel in img.pixels_mut() {
+        pixel.data[0] = lut[pixel.data[0] as usize];
+    }
+    img
+}
+
+/// Table of all value corrections from settings, composed in their order
+pub fn lookup_table(settings: &PreprocessSettings) -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    for (i, value) in lut.iter_mut().enumerate() {
+        let mut v = i as f32 / 255.0;
+        if settings.invert {
+            v = 1.0 - v;
+        }
+        if let Some((black, white)) = settings.levels {
+            v = ((v * 255.0 - f32::from(black)) / f32::from(white - black)).clamp(0.0, 1.0);
+        }
+        if let Some(gamma) = settings.gamma {
+            v = v.powf(1.0 / gamma);
+        }
+        if let Some(ref points) = settings.curve {
+            v = curve(points, v * 255.0) / 255.0;
+        }
+        *value = (v * 255.0).round() as u8;
+    }
+    lut
+}
+
+/// Piecewise linear interpolation of control points, flat beyond the first and last one
+fn curve(points: &[(u8, u8)], value: f32) -> f32 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return value,
+    };
+    if value <= f32::from(first.0) {
+        return f32::from(first.1);
+    }
+    for pair in points.windows(2) {
+        let (x0, y0) = (f32::from(pair[0].0), f32::from(pair[0].1));
+        let (x1, y1) = (f32::from(pair[1].0), f32::from(pair[1].1));
+        if value <= x1 && x1 > x0 {
+            return y0 + (value - x0) / (x1 - x0) * (y1 - y0);
+        }
+    }
+    f32::from(last.1)
+}
+
+/// Median of square window around every pixel, window is clamped to the image.
+/// Rows are computed in parallel with a sliding histogram.
+pub fn median(img: &GrayImage, radius: u32) -> GrayImage {
+    let (width, height) = img.dimensions();
+    let radius = radius as i64;
+    let value = |x: i64, y: i64| img.get_pixel(x.max(0).min(width as i64 - 1) as u32, y.max(0).min(height as i64 - 1) as u32).data[0];
+    let window = ((2 * radius + 1) * (2 * radius + 1)) as u32;
+
+    let rows: Vec<Vec<u8>> = (0..height as i64)
+        .into_par_iter()
+        .map(|y| {
+            let mut histogram = [0u32; 256];
+            for wy in y - radius..=y + radius {
+                for wx in -radius..=radius {
+                    histogram[value(wx, wy) as usize] += 1;
+                }
+            }
+            let mut row = Vec::with_capacity(width as usize);
+            for x in 0..width as i64 {
+                if x > 0 {
+                    // slide the window one pixel to the right
+                    for wy in y - radius..=y + radius {
+                        histogram[value(x - radius - 1, wy) as usize] -= 1;
+                        histogram[value(x + radius, wy) as usize] += 1;
+                    }
+                }
+                let mut count = 0;
+                let mut median = 255;
+                for (v, n) in histogram.iter().enumerate() {
+                    count += n;
+                    if count * 2 > window {
+                        median = v as u8;
+                        break;
+                    }
+                }
+                row.push(median);
+            }
+            row
+        }).collect();
+    ImageBuffer::from_fn(width, height, |x, y| Luma([rows[y as usize][x as usize]]))
+}
@@ -0,0 +1,331 @@
+use settings::OutputSettings;
+use std::f32;
+
+/// Transfer function from normalized distance to output value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Falloff {
+    Linear,
+    Smoothstep,
+    /// exponential decay with given rate
+    Exponential(f32),
+    /// power of the linear ramp with given exponent
+    Power(f32),
+    /// inverse distance with given steepness
+    Inverse(f32),
+}
+
+impl Falloff {
+    /// Parse name with optional parameter after colon, e.g. `power:3`
+    pub fn from_name(name: &str) -> Option<Falloff> {
+        let mut parts = name.trim().splitn(2, ':');
+        let kind = parts.next().unwrap_or("").to_lowercase();
+        let param = match parts.next() {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val > 0.0 => Some(val),
+                _ => return None,
+            },
+            None => None,
+        };
+        match kind.as_str() {
+            "linear" => Some(Falloff::Linear),
+            "smoothstep" => Some(Falloff::Smoothstep),
+            "exponential" | "exp" => Some(Falloff::Exponential(param.unwrap_or(4.0))),
+            "power" | "pow" => Some(Falloff::Power(param.unwrap_or(2.0))),
+            "inverse" => Some(Falloff::Inverse(param.unwrap_or(4.0))),
+            _ => None,
+        }
+    }
+
+    /// Falloff of normalized distance `t` in 0-1 range, zero at the surface and one at the end
+    fn falloff(self, t: f32) -> f32 {
+        match self {
+            Falloff::Linear => t,
+            Falloff::Smoothstep => t * t * (3.0 - 2.0 * t),
+            Falloff::Power(exponent) => 1.0 - (1.0 - t).powf(exponent),
+            Falloff::Exponential(rate) => {
+                let end = (-rate).exp();
+                1.0 - ((-rate * t).exp() - end) / (1.0 - end)
+            }
+            Falloff::Inverse(steepness) => {
+                let end = 1.0 / (1.0 + steepness);
+                1.0 - (1.0 / (1.0 + steepness * t) - end) / (1.0 - end)
+            }
+        }
+    }
+}
+
+/// Distance where output reaches its end, clamp distance or the largest distance
+pub fn normalization_distance(output: &OutputSettings, max: f32) -> f32 {
+    output.clamp_distance.unwrap_or(max)
+}
+
+/// Falloff of distance in 0-1 range, zero at the surface
+fn falloff(output: &OutputSettings, dst: f32, max: f32) -> f32 {
+    let range = normalization_distance(output, max);
+    let t = (dst / range).min(1.0);
+    match output.expression {
+        Some(ref expression) => 1.0 - expression.eval(&Vars { d: dst, r: range, t }).clamp(0.0, 1.0),
+        None => output.falloff.falloff(t),
+    }
+}
+
+/// Output value of distance in 0-1 range, surface is white unless the output is not inverted
+pub fn distance_to_value(output: &OutputSettings, dst: f32, max: f32) -> f32 {
+    let falloff = falloff(output, dst, max);
+    if output.invert {
+        1.0 - falloff
+    } else {
+        falloff
+    }
+}
+
+/// Output pixel value of distance
+pub fn distance_to_byte(output: &OutputSettings, dst: f32, max: f32) -> u8 {
+    let falloff = (falloff(output, dst, max) * 255.0) as u8;
+    if output.invert {
+        255 - falloff
+    } else {
+        falloff
+    }
+}
+
+/// Values of expression variables
+pub struct Vars {
+    /// distance
+    pub d: f32,
+    /// normalization distance
+    pub r: f32,
+    /// distance divided by normalization distance, at most one
+    pub t: f32,
+}
+
+/// Parsed remapping expression, evaluates to output value where one is the surface
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(f32),
+    Var(char),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+impl Expr {
+    /// Parse expression with `+ - * / ^`, parentheses, variables `d`, `r`, `t`, constants `pi`
+    /// and `e` and functions `abs sqrt exp ln sin cos floor min max pow clamp smoothstep`
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            chars: text.chars().filter(|c| !c.is_whitespace()).collect(),
+            position: 0,
+        };
+        let expr = parser.sum()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(c) => Err(format!("unexpected '{}'", c)),
+        }
+    }
+
+    pub fn eval(&self, vars: &Vars) -> f32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Var('d') => vars.d,
+            Expr::Var('r') => vars.r,
+            Expr::Var(_) => vars.t,
+            Expr::Neg(expr) => -expr.eval(vars),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(vars), b.eval(vars));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            }
+            Expr::Call(name, args) => {
+                let args: Vec<f32> = args.iter().map(|arg| arg.eval(vars)).collect();
+                match (name.as_str(), args.as_slice()) {
+                    ("abs", [x]) => x.abs(),
+                    ("sqrt", [x]) => x.sqrt(),
+                    ("exp", [x]) => x.exp(),
+                    ("ln", [x]) => x.ln(),
+                    ("sin", [x]) => x.sin(),
+                    ("cos", [x]) => x.cos(),
+                    ("floor", [x]) => x.floor(),
+                    ("min", [a, b]) => a.min(*b),
+                    ("max", [a, b]) => a.max(*b),
+                    ("pow", [a, b]) => a.powf(*b),
+                    ("clamp", [x, low, high]) => x.max(*low).min(*high),
+                    ("smoothstep", [low, high, x]) => {
+                        let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+                        t * t * (3.0 - 2.0 * t)
+                    }
+                    _ => f32::NAN,
+                }
+            }
+        }
+    }
+}
+
+/// Number of arguments of known functions
+fn arity(name: &str) -> Option<usize> {
+    match name {
+        "abs" | "sqrt" | "exp" | "ln" | "sin" | "cos" | "floor" => Some(1),
+        "min" | "max" | "pow" => Some(2),
+        "clamp" | "smoothstep" => Some(3),
+        _ => None,
+    }
+}
+
+/// Recursive descent parser, every level handles one operator precedence
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(c) => Err(format!("expected '{}', found '{}'", expected, c)),
+            None => Err(format!("expected '{}' at the end", expected)),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.position += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.peek().filter(|c| *c == '*' || *c == '/') {
+            self.position += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some('-') {
+            self.position += 1;
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    /// Power is right associative and binds tighter than unary minus on its left
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.atom()?;
+        if self.peek() == Some('^') {
+            self.position += 1;
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expr = self.sum()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.position;
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.position += 1;
+                }
+                let text: String = self.chars[start..self.position].iter().collect();
+                text.parse::<f32>().map(Expr::Number).map_err(|_| format!("invalid number {}", text))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.position;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+                    self.position += 1;
+                }
+                let name: String = self.chars[start..self.position].iter().collect();
+                match name.as_str() {
+                    "d" | "r" | "t" => return Ok(Expr::Var(name.chars().next().unwrap())),
+                    "pi" => return Ok(Expr::Number(f32::consts::PI)),
+                    "e" => return Ok(Expr::Number(f32::consts::E)),
+                    _ => (),
+                }
+                let count = arity(&name).ok_or_else(|| format!("unknown name {}", name))?;
+                self.expect('(')?;
+                let mut args = vec![self.sum()?];
+                while self.peek() == Some(',') {
+                    self.position += 1;
+                    args.push(self.sum()?);
+                }
+                self.expect(')')?;
+                if args.len() != count {
+                    return Err(format!("{} takes {} arguments", name, count));
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err(String::from("unexpected end")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> f32 {
+        Expr::parse(text).unwrap().eval(&Vars { d: 2.0, r: 8.0, t: 0.25 })
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("8 - 4 - 2"), 2.0);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+        assert_eq!(eval("-2 ^ 2"), -4.0);
+        assert_eq!(eval("2 * -3"), -6.0);
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("2 ^ -1"), 0.5);
+    }
+
+    #[test]
+    fn variables_and_functions() {
+        assert_eq!(eval("d / r"), 0.25);
+        assert_eq!(eval("1 - t"), 0.75);
+        assert_eq!(eval("max(d, r) + min(d, r)"), 10.0);
+        assert_eq!(eval("clamp(d, 0, 1)"), 1.0);
+        assert_eq!(eval("smoothstep(0, 1, 0.5)"), 0.5);
+        assert_eq!(eval("sqrt(abs(-16))"), 4.0);
+        assert!((eval("cos(pi)") + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Expr::parse("").is_err());
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("(1 + 2").is_err());
+        assert!(Expr::parse("1 (2)").is_err());
+        assert!(Expr::parse("x + 1").is_err());
+        assert!(Expr::parse("foo(1)").is_err());
+        assert!(Expr::parse("min(1)").is_err());
+        assert!(Expr::parse("clamp(1, 2)").is_err());
+        assert!(Expr::parse("1..2").is_err());
+    }
+}
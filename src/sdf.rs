@@ -0,0 +1,264 @@
+use rayon::prelude::*;
+use std::f32;
+use trimesh::TriMesh;
+use vec3::Vec3;
+use volume::Volume;
+
+/// Empty voxels added around the mesh bounds
+const PADDING: usize = 2;
+/// Maximal number of triangles in one leaf of the bounding volume hierarchy
+const LEAF_SIZE: usize = 4;
+
+/// Compute signed distance volume of a closed triangle mesh, negative inside. Longest side of the
+/// mesh gets `resolution` voxels. Sign is found by ray parity along Z, so the mesh has to be
+/// watertight, distance is exact distance to the nearest triangle.
+pub fn mesh_sdf(mesh: &TriMesh, resolution: usize) -> Volume {
+    let (min, max) = mesh.bounds();
+    let size = &max - &min;
+    let extent = size.x.max(size.y).max(size.z).max(f32::EPSILON);
+    let voxel_size = extent / (resolution.max(2) - 1) as f32;
+    let dims = (
+        (size.x / voxel_size).ceil() as usize + 1 + 2 * PADDING,
+        (size.y / voxel_size).ceil() as usize + 1 + 2 * PADDING,
+        (size.z / voxel_size).ceil() as usize + 1 + 2 * PADDING,
+    );
+    let origin = &min - &(Vec3::new((1.0, 1.0, 1.0)) * (PADDING as f32 * voxel_size));
+    let mut volume = Volume::new(dims, origin, Vec3::new((voxel_size, voxel_size, voxel_size)));
+    let bvh = Bvh::new(mesh);
+
+    // columns along Z share ray parity test
+    let columns: Vec<Vec<f32>> = (0..dims.0 * dims.1)
+        .into_par_iter()
+        .map(|column| {
+            let (x, y) = (column % dims.0, column / dims.0);
+            let bottom = volume.position(x, y, 0);
+            // small offset, so rays don't hit edges between triangles exactly
+            let mut hits = Vec::new();
+            bvh.column_hits(bottom.x + voxel_size * 1.37e-4, bottom.y + voxel_size * 0.71e-4, &mut hits);
+            hits.sort_unstable_by(|a, b| a.total_cmp(b));
+
+            let mut above = 0;
+            (0..dims.2)
+                .map(|z| {
+                    let point = volume.position(x, y, z);
+                    while above < hits.len() && hits[above] <= point.z {
+                        above += 1;
+                    }
+                    let dst = bvh.nearest_distance(&point);
+                    // odd number of crossings above the point means it is inside
+                    if (hits.len() - above) % 2 == 1 {
+                        -dst
+                    } else {
+                        dst
+                    }
+                }).collect()
+        }).collect();
+    for (column, values) in columns.iter().enumerate() {
+        let (x, y) = (column % dims.0, column / dims.0);
+        for (z, value) in values.iter().enumerate() {
+            let index = volume.index(x, y, z);
+            volume.data[index] = *value;
+        }
+    }
+    volume
+}
+
+/// Closest point of triangle abc to point p
+pub fn closest_point_on_triangle(p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a.clone();
+    }
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b.clone();
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + &(ab * (d1 / (d1 - d3)));
+    }
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c.clone();
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + &(ac * (d2 / (d2 - d6)));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + &((c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6))));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + &(&ab * (vb * denom) + ac * (vc * denom))
+}
+
+#[derive(Debug)]
+struct Node {
+    min: Vec3,
+    max: Vec3,
+    /// children for inner nodes, range of triangles for leaves
+    left: usize,
+    right: usize,
+    leaf: bool,
+}
+
+/// Bounding volume hierarchy over mesh triangles
+struct Bvh<'a> {
+    mesh: &'a TriMesh,
+    nodes: Vec<Node>,
+    triangles: Vec<u32>,
+}
+
+impl<'a> Bvh<'a> {
+    fn new(mesh: &'a TriMesh) -> Bvh<'a> {
+        let mut bvh = Bvh {
+            mesh,
+            nodes: Vec::new(),
+            triangles: (0..mesh.faces.len() as u32).collect(),
+        };
+        if !mesh.faces.is_empty() {
+            let centroids: Vec<Vec3> = mesh
+                .faces
+                .iter()
+                .map(|face| {
+                    let (a, b, c) = bvh.corners(face);
+                    &(a + b) + c
+                }).collect();
+            let count = bvh.triangles.len();
+            bvh.build(0, count, &centroids);
+        }
+        bvh
+    }
+
+    fn corners(&self, face: &[u32; 3]) -> (&Vec3, &Vec3, &Vec3) {
+        (
+            &self.mesh.verts[face[0] as usize],
+            &self.mesh.verts[face[1] as usize],
+            &self.mesh.verts[face[2] as usize],
+        )
+    }
+
+    /// Build node over given range of triangles, split at median of the longest axis, returns node index
+    fn build(&mut self, start: usize, end: usize, centroids: &[Vec3]) -> usize {
+        let mut min = Vec3::new((f32::MAX, f32::MAX, f32::MAX));
+        let mut max = Vec3::new((f32::MIN, f32::MIN, f32::MIN));
+        for triangle in &self.triangles[start..end] {
+            let (a, b, c) = self.corners(&self.mesh.faces[*triangle as usize]);
+            min = min.min(a).min(b).min(c);
+            max = max.max(a).max(b).max(c);
+        }
+        let index = self.nodes.len();
+        if end - start <= LEAF_SIZE {
+            self.nodes.push(Node {
+                min,
+                max,
+                left: start,
+                right: end,
+                leaf: true,
+            });
+            return index;
+        }
+        let size = &max - &min;
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        self.triangles[start..end].sort_unstable_by(|a, b| centroids[*a as usize][axis].total_cmp(&centroids[*b as usize][axis]));
+        self.nodes.push(Node {
+            min,
+            max,
+            left: 0,
+            right: 0,
+            leaf: false,
+        });
+        let middle = (start + end) / 2;
+        let left = self.build(start, middle, centroids);
+        let right = self.build(middle, end, centroids);
+        self.nodes[index].left = left;
+        self.nodes[index].right = right;
+        index
+    }
+
+    /// Distance to the nearest triangle
+    fn nearest_distance(&self, point: &Vec3) -> f32 {
+        if self.nodes.is_empty() {
+            return f32::MAX;
+        }
+        let mut best = f32::MAX;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if box_distance_sqr(point, node) >= best {
+                continue;
+            }
+            if node.leaf {
+                for triangle in &self.triangles[node.left..node.right] {
+                    let (a, b, c) = self.corners(&self.mesh.faces[*triangle as usize]);
+                    let dst = (point - &closest_point_on_triangle(point, a, b, c)).len_sqr();
+                    best = best.min(dst);
+                }
+            } else {
+                // visit closer child first
+                let (left, right) = (&self.nodes[node.left], &self.nodes[node.right]);
+                if box_distance_sqr(point, left) < box_distance_sqr(point, right) {
+                    stack.push(node.right);
+                    stack.push(node.left);
+                } else {
+                    stack.push(node.left);
+                    stack.push(node.right);
+                }
+            }
+        }
+        best.sqrt()
+    }
+
+    /// Heights where vertical line through (x, y) crosses the mesh
+    fn column_hits(&self, x: f32, y: f32, hits: &mut Vec<f32>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if x < node.min.x || x > node.max.x || y < node.min.y || y > node.max.y {
+                continue;
+            }
+            if !node.leaf {
+                stack.push(node.left);
+                stack.push(node.right);
+                continue;
+            }
+            for triangle in &self.triangles[node.left..node.right] {
+                let (a, b, c) = self.corners(&self.mesh.faces[*triangle as usize]);
+                let det = (b.y - c.y) * (a.x - c.x) + (c.x - b.x) * (a.y - c.y);
+                if det.abs() < f32::EPSILON {
+                    continue;
+                }
+                let l1 = ((b.y - c.y) * (x - c.x) + (c.x - b.x) * (y - c.y)) / det;
+                let l2 = ((c.y - a.y) * (x - c.x) + (a.x - c.x) * (y - c.y)) / det;
+                let l3 = 1.0 - l1 - l2;
+                if l1 >= 0.0 && l2 >= 0.0 && l3 >= 0.0 {
+                    hits.push(l1 * a.z + l2 * b.z + l3 * c.z);
+                }
+            }
+        }
+    }
+}
+
+/// Squared distance from point to bounding box of node, zero inside
+fn box_distance_sqr(point: &Vec3, node: &Node) -> f32 {
+    let outside = (&node.min - point).max(&(point - &node.max)).max(&Vec3::new((0.0, 0.0, 0.0)));
+    outside.len_sqr()
+}
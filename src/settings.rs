@@ -1,48 +1,100 @@
+use channel::ChannelSource;
 use clap::ArgMatches;
+use colormap::Colormap;
+use generator::Region;
+use remap::{Expr, Falloff};
 use std::io;
+use std::path::Path;
 
 const DEF_RADIUS: usize = 64;
+const DEF_RESOLUTION: u32 = 1024;
+const DEF_VOLUME_RESOLUTION: usize = 64;
+const DEF_BASE_DEPTH: f32 = 2.0;
+const DEF_LOD_ERROR: f32 = 1.0;
+const DEF_BAND_ROWS: usize = 256;
+const DEF_JOBS: usize = 4;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GenSettings {
     pub radius: usize,
     pub repeat: ImgRepeat,
     pub height_setting: CaptureHeight,
     pub img_height_mult: f32,
+    pub export: ExportSettings,
+    pub preprocess: PreprocessSettings,
+    pub output: OutputSettings,
+    /// longer side of heightmap rasterized from input mesh
+    pub input_resolution: u32,
+    /// voxels on the longest side of volumetric distance field of meshes, or number of slices
+    /// for heightmaps, computed only when set
+    pub volume_resolution: Option<usize>,
+    /// pack multiple capture heights into channels of one RGBA image
+    pub pack: bool,
+    /// radii computed in one run from the mesh of the largest one, which is `radius`,
+    /// empty when only `radius` is computed
+    pub radii: Vec<usize>,
+    /// channel of the input used as height, image is used as it is when not set
+    pub channel: Option<ChannelSource>,
+    /// process every channel of the input independently
+    pub per_channel: bool,
+    /// radius of every channel in per channel mode, `radius` is used for missing ones
+    pub channel_radii: Vec<usize>,
+    /// capture height of every channel in per channel mode, `height_setting` is used for missing ones
+    pub channel_heights: Vec<CaptureHeight>,
+    /// vertices over masked out pixels are ignored as surface
+    pub mask: Option<MaskSource>,
+    /// masked out pixels are not computed and get `mask_fill` value
+    pub skip_masked: bool,
+    pub mask_fill: u8,
+    /// rows in one band of streamed processing, whole image is processed in memory when not set
+    pub stream_rows: Option<usize>,
+    /// only pixels in the region are computed
+    pub region: Option<Region>,
+    /// previous output is recomputed only where changes in the region can reach
+    pub dirty: Option<Region>,
+    /// previous output to patch, output file itself when not set
+    pub previous: Option<String>,
+    /// number of job files rows of the image are split into, computed right away when not set
+    pub split: Option<usize>,
+    /// directory of outputs reused by runs with the same input pixels and settings
+    pub cache_dir: Option<String>,
 }
 
 impl GenSettings {
     pub fn new_from_input(matches: &ArgMatches) -> GenSettings {
-        let radius: usize = match matches.value_of("radius") {
-            Some(value) => match value.trim().parse::<usize>() {
+        let radii: Vec<usize> = match matches.value_of("radius") {
+            Some(value) => match value.split(',').map(|radius| radius.trim().parse::<usize>()).collect() {
                 Ok(val) => val,
                 Err(_) => {
-                    eprintln!("Invalid radius, setting {}", DEF_RADIUS);
-                    DEF_RADIUS
+                    warn!("Invalid radius, setting {}", DEF_RADIUS);
+                    vec![DEF_RADIUS]
                 }
             },
             None => {
                 let r = GenSettings::get_radius_input();
-                println!("-------------------------");
-                r
+                eprintln!("-------------------------");
+                vec![r]
             }
         };
+        let radius = radii.iter().cloned().max().unwrap_or(DEF_RADIUS);
         let repeat: ImgRepeat = match matches.occurrences_of("clamp") {
             1 => ImgRepeat::Clamp,
             _ => match matches.occurrences_of("repeat") {
                 1 => ImgRepeat::Repeat,
+                // tile meshes are padded like repeated images, from their neighbours
+                _ if matches.is_present("tiles") => ImgRepeat::Repeat,
                 _ => {
                     let r = GenSettings::get_repeat_input();
-                    println!("-------------------------");
+                    eprintln!("-------------------------");
                     r
                 }
             },
         };
         let height_setting: CaptureHeight = match matches.value_of("height") {
-            Some(value) => match value.trim().parse::<u8>() {
-                Ok(val) => CaptureHeight::UserDefined(val),
-                Err(_) => {
-                    eprintln!("Invalid height, setting automatic");
+            Some(value) => match CaptureHeight::parse(value) {
+                Some(val) => val,
+                None => {
+                    warn!("Invalid height, setting automatic");
                     CaptureHeight::Generated
                 }
             },
@@ -50,7 +102,7 @@ impl GenSettings {
                 1 => CaptureHeight::Generated,
                 _ => {
                     let h = GenSettings::get_height_input();
-                    println!("-------------------------");
+                    eprintln!("-------------------------");
                     h
                 }
             },
@@ -59,17 +111,145 @@ impl GenSettings {
             Some(value) => match value.trim().parse::<f32>() {
                 Ok(val) => val,
                 Err(_) => {
-                    eprintln!("Invalid multiplier, setting {}", 1.0);
+                    warn!("Invalid multiplier, setting {}", 1.0);
                     1.0
                 }
             },
             None => {
                 let h = GenSettings::get_height_mult();
-                println!("-------------------------");
+                eprintln!("-------------------------");
                 h
             }
         };
-        GenSettings::new_from_values((radius, repeat, height_setting, height_mult))
+        let input_resolution: u32 = match matches.value_of("resolution") {
+            Some(value) => match value.trim().parse::<u32>() {
+                Ok(val) if val > 0 => val,
+                _ => {
+                    warn!("Invalid resolution, setting {}", DEF_RESOLUTION);
+                    DEF_RESOLUTION
+                }
+            },
+            None => DEF_RESOLUTION,
+        };
+        let volume_resolution = match matches.value_of("volume") {
+            Some(value) => match value.trim().parse::<usize>() {
+                Ok(val) if val > 1 => Some(val),
+                _ => {
+                    warn!("Invalid volume resolution, setting {}", DEF_VOLUME_RESOLUTION);
+                    Some(DEF_VOLUME_RESOLUTION)
+                }
+            },
+            None => None,
+        };
+        let mut settings = GenSettings::new_from_values((radius, repeat, height_setting, height_mult));
+        settings.export = ExportSettings::new_from_input(matches);
+        settings.preprocess = PreprocessSettings::new_from_input(matches);
+        settings.output = OutputSettings::new_from_input(matches);
+        settings.input_resolution = input_resolution;
+        settings.volume_resolution = volume_resolution;
+        settings.pack = matches.is_present("pack");
+        if radii.len() > 1 {
+            settings.radii = radii;
+        }
+        settings.channel = match matches.value_of("channel") {
+            Some(value) => match ChannelSource::from_name(value) {
+                Some(val) => Some(val),
+                None => {
+                    warn!("Invalid channel, setting red");
+                    Some(ChannelSource::Red)
+                }
+            },
+            None => None,
+        };
+        settings.per_channel = matches.is_present("per_channel");
+        settings.mask = match matches.value_of("mask") {
+            Some(filename) => Some(MaskSource::Image(String::from(filename))),
+            None if matches.is_present("alpha_mask") => Some(MaskSource::Alpha),
+            None => None,
+        };
+        settings.skip_masked = matches.is_present("skip_masked");
+        settings.mask_fill = match matches.value_of("mask_fill") {
+            Some(value) => match value.trim().parse::<u8>() {
+                Ok(val) => val,
+                Err(_) => {
+                    warn!("Invalid mask fill, setting 0");
+                    0
+                }
+            },
+            None => 0,
+        };
+        settings.stream_rows = match (matches.is_present("stream"), matches.value_of("band_rows")) {
+            (false, _) => None,
+            (true, Some(value)) => match value.trim().parse::<usize>() {
+                Ok(val) if val > 0 => Some(val),
+                _ => {
+                    warn!("Invalid band rows, setting {}", DEF_BAND_ROWS);
+                    Some(DEF_BAND_ROWS)
+                }
+            },
+            (true, None) => Some(DEF_BAND_ROWS),
+        };
+        settings.region = match matches.value_of("region") {
+            Some(value) => match Region::parse(value) {
+                Some(val) => Some(val),
+                None => {
+                    warn!("Invalid region, setting whole image");
+                    None
+                }
+            },
+            None => None,
+        };
+        settings.dirty = match matches.value_of("dirty") {
+            Some(value) => match Region::parse(value) {
+                Some(val) => Some(val),
+                None => {
+                    warn!("Invalid dirty region, setting whole image");
+                    None
+                }
+            },
+            None => None,
+        };
+        settings.previous = matches.value_of("previous").map(String::from);
+        settings.cache_dir = matches.value_of("cache_dir").map(String::from);
+        settings.split = match matches.value_of("split") {
+            Some(value) => match value.trim().parse::<usize>() {
+                Ok(val) if val > 0 => Some(val),
+                _ => {
+                    warn!("Invalid job count, setting {}", DEF_JOBS);
+                    Some(DEF_JOBS)
+                }
+            },
+            None => None,
+        };
+        settings.channel_radii = match matches.value_of("channel_radii") {
+            Some(value) => match value.split(',').map(|radius| radius.trim().parse::<usize>()).collect() {
+                Ok(val) => val,
+                Err(_) => {
+                    warn!("Invalid channel radii, setting {} for all channels", settings.radius);
+                    Vec::new()
+                }
+            },
+            None => Vec::new(),
+        };
+        settings.channel_heights = match matches.value_of("channel_heights") {
+            Some(value) => {
+                let heights: Option<Vec<CaptureHeight>> = value
+                    .split(',')
+                    .map(|height| match height.trim() {
+                        "auto" => Some(CaptureHeight::Generated),
+                        height => height.parse::<u8>().ok().map(CaptureHeight::UserDefined),
+                    }).collect();
+                match heights {
+                    Some(val) => val,
+                    None => {
+                        warn!("Invalid channel heights, setting {:?} for all channels", settings.height_setting);
+                        Vec::new()
+                    }
+                }
+            }
+            None => Vec::new(),
+        };
+        settings
     }
 
     pub fn new_from_values(values: (usize, ImgRepeat, CaptureHeight, f32)) -> GenSettings {
@@ -78,6 +258,26 @@ impl GenSettings {
             repeat: values.1,
             height_setting: values.2,
             img_height_mult: values.3,
+            export: ExportSettings::new(),
+            preprocess: PreprocessSettings::new(),
+            output: OutputSettings::new(),
+            input_resolution: DEF_RESOLUTION,
+            volume_resolution: None,
+            pack: false,
+            radii: Vec::new(),
+            channel: None,
+            per_channel: false,
+            channel_radii: Vec::new(),
+            channel_heights: Vec::new(),
+            mask: None,
+            skip_masked: false,
+            mask_fill: 0,
+            stream_rows: None,
+            region: None,
+            dirty: None,
+            previous: None,
+            split: None,
+            cache_dir: None,
         }
     }
 
@@ -87,33 +287,76 @@ impl GenSettings {
             repeat: ImgRepeat::Repeat,
             height_setting: CaptureHeight::Generated,
             img_height_mult: 1.0,
+            export: ExportSettings::new(),
+            preprocess: PreprocessSettings::new(),
+            output: OutputSettings::new(),
+            input_resolution: DEF_RESOLUTION,
+            volume_resolution: None,
+            pack: false,
+            radii: Vec::new(),
+            channel: None,
+            per_channel: false,
+            channel_radii: Vec::new(),
+            channel_heights: Vec::new(),
+            mask: None,
+            skip_masked: false,
+            mask_fill: 0,
+            stream_rows: None,
+            region: None,
+            dirty: None,
+            previous: None,
+            split: None,
+            cache_dir: None,
+        }
+    }
+
+    /// Settings for one channel in per channel mode
+    pub fn for_channel(&self, index: usize) -> GenSettings {
+        let mut settings = self.clone();
+        settings.per_channel = false;
+        if let Some(radius) = self.channel_radii.get(index) {
+            settings.radius = *radius;
+            settings.radii = Vec::new();
+        }
+        if let Some(height) = self.channel_heights.get(index) {
+            settings.height_setting = height.clone();
+        }
+        settings
+    }
+
+    /// Radii of all computed layers
+    pub fn layer_radii(&self) -> Vec<usize> {
+        if self.radii.is_empty() {
+            vec![self.radius]
+        } else {
+            self.radii.clone()
         }
     }
 
     fn get_radius_input() -> usize {
         let mut radius = String::new();
-        println!("Please input search radius (preferably power of two), default is {}.", DEF_RADIUS);
+        eprintln!("Please input search radius (preferably power of two), default is {}.", DEF_RADIUS);
         io::stdin().read_line(&mut radius).expect("Failed to read input");
         if radius.trim().is_empty() {
-            println!("Setting {} as a radius.", DEF_RADIUS);
+            info!("Setting {} as a radius", DEF_RADIUS);
             return DEF_RADIUS;
         }
         match radius.trim().parse::<usize>() {
             Ok(value) => value,
             Err(_) => {
-                eprintln!("Invalid number, setting {} as a radius", DEF_RADIUS);
+                warn!("Invalid number, setting {} as a radius", DEF_RADIUS);
                 DEF_RADIUS
             }
         }
     }
 
     fn get_repeat_input() -> ImgRepeat {
-        println!("Please input image repeat option, default is Repeat.");
-        println!("1 - Repeat, 2 - Clamp");
+        eprintln!("Please input image repeat option, default is Repeat.");
+        eprintln!("1 - Repeat, 2 - Clamp");
         let mut input = String::new();
         io::stdin().read_line(&mut input).expect("Failed to read input");
         if input.trim().is_empty() {
-            println!("Setting Repeat.");
+            info!("Setting Repeat");
             return ImgRepeat::Repeat;
         }
         match input.trim().parse::<u8>() {
@@ -121,46 +364,46 @@ impl GenSettings {
                 1 => ImgRepeat::Repeat,
                 2 => ImgRepeat::Clamp,
                 _ => {
-                    eprintln!("Invalid option, setting Repeat");
+                    warn!("Invalid option, setting Repeat");
                     ImgRepeat::Repeat
                 }
             },
             Err(_) => {
-                eprintln!("Invalid input, setting Repeat");
+                warn!("Invalid input, setting Repeat");
                 ImgRepeat::Repeat
             }
         }
     }
 
     fn get_height_input() -> CaptureHeight {
-        println!("Please input capture height, or press enter for automatic computation.");
+        eprintln!("Please input capture height, or press enter for automatic computation.");
         let mut input = String::new();
         io::stdin().read_line(&mut input).expect("Failed to read input");
         if input.trim().is_empty() {
-            println!("Setting automatic.");
+            info!("Setting automatic");
             return CaptureHeight::Generated;
         }
-        match input.trim().parse::<u8>() {
-            Ok(value) => CaptureHeight::UserDefined(value),
-            Err(_) => {
-                eprintln!("Invalid input, setting automatic");
+        match CaptureHeight::parse(&input) {
+            Some(value) => value,
+            None => {
+                warn!("Invalid input, setting automatic");
                 CaptureHeight::Generated
             }
         }
     }
 
     fn get_height_mult() -> f32 {
-        println!("Please input height multipiler, 1.0 is radius height and default.");
+        eprintln!("Please input height multipiler, 1.0 is radius height and default.");
         let mut input = String::new();
         io::stdin().read_line(&mut input).expect("Failed to read input");
         if input.trim().is_empty() {
-            println!("Setting 1.0x.");
+            info!("Setting 1.0x");
             return 1.0;
         }
         match input.trim().parse::<f32>() {
             Ok(value) => value,
             Err(_) => {
-                eprintln!("Invalid value, setting 1.0x.");
+                warn!("Invalid value, setting 1.0x.");
                 1.0
             }
         }
@@ -173,14 +416,440 @@ impl Default for GenSettings {
     }
 }
 
-#[derive(Debug)]
+/// Source of mask excluding pixels from the surface, zero values are masked out
+#[derive(Debug, Clone)]
+pub enum MaskSource {
+    /// alpha channel of the input
+    Alpha,
+    /// grayscale image of the same size as the input
+    Image(String),
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum ImgRepeat {
     Repeat,
     Clamp,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CaptureHeight {
     UserDefined(u8),
     Generated,
+    /// several heights computed in one pass, each gives its own output
+    Multiple(Vec<u8>),
+}
+
+impl CaptureHeight {
+    /// Parse one height or comma separated list of heights
+    pub fn parse(value: &str) -> Option<CaptureHeight> {
+        let heights: Result<Vec<u8>, _> = value.split(',').map(|height| height.trim().parse::<u8>()).collect();
+        match heights {
+            Ok(ref heights) if heights.len() == 1 => Some(CaptureHeight::UserDefined(heights[0])),
+            Ok(heights) => Some(CaptureHeight::Multiple(heights)),
+            Err(_) => None,
+        }
+    }
+
+    /// Highest capture height, `generated` is used for automatic height
+    pub fn max_value(&self, generated: u8) -> u8 {
+        match self {
+            CaptureHeight::UserDefined(val) => *val,
+            CaptureHeight::Generated => generated,
+            CaptureHeight::Multiple(values) => values.iter().cloned().max().unwrap_or(generated),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    pub format: Option<MeshFormat>,
+    /// image referenced as texture by exported OBJ material
+    pub texture: Option<String>,
+    /// maximal vertical error of simplified mesh, full grid is exported when not set
+    pub simplify: Option<f32>,
+    /// close the surface with walls and a base
+    pub solid: bool,
+    /// distance of the base under the lowest vertex, in output units
+    pub base_depth: f32,
+    /// length of the longer side in millimetres, replaces default export scale
+    pub print_size: Option<f32>,
+    /// size of one pixel in output units
+    pub scale: Option<f32>,
+    pub up_axis: UpAxis,
+    /// move center of the mesh to origin
+    pub center: bool,
+    pub region: ExportRegion,
+    /// number of exported levels of detail
+    pub lod_levels: Option<usize>,
+    /// maximal error of the first simplified level of detail
+    pub lod_error: f32,
+    /// colormap of vertex colors, vertex colors are not exported when not set
+    pub colormap: Option<Colormap>,
+}
+
+impl ExportSettings {
+    pub fn new_from_input(matches: &ArgMatches) -> ExportSettings {
+        let format = match matches.value_of("mesh_format") {
+            Some(value) => match MeshFormat::from_name(value) {
+                Some(format) => Some(format),
+                None => {
+                    warn!("Invalid mesh format, using file extension");
+                    None
+                }
+            },
+            None => None,
+        };
+        let simplify = match matches.value_of("simplify") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val >= 0.0 => Some(val),
+                _ => {
+                    warn!("Invalid simplification error, exporting full mesh");
+                    None
+                }
+            },
+            None => None,
+        };
+        let base_depth = match matches.value_of("base_depth") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val >= 0.0 => val,
+                _ => {
+                    warn!("Invalid base depth, setting {}", DEF_BASE_DEPTH);
+                    DEF_BASE_DEPTH
+                }
+            },
+            None => DEF_BASE_DEPTH,
+        };
+        let print_size = match matches.value_of("print_size") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val > 0.0 => Some(val),
+                _ => {
+                    warn!("Invalid print size, using default scale");
+                    None
+                }
+            },
+            None => None,
+        };
+        let scale = match matches.value_of("export_scale") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val > 0.0 => Some(val),
+                _ => {
+                    warn!("Invalid export scale, using default");
+                    None
+                }
+            },
+            None => None,
+        };
+        let up_axis = match matches.value_of("up") {
+            Some("y") => UpAxis::Y,
+            _ => UpAxis::Z,
+        };
+        let region = match matches.value_of("export_region") {
+            Some("core") => ExportRegion::Core,
+            _ => ExportRegion::Padded,
+        };
+        let lod_levels = match matches.value_of("lod") {
+            Some(value) => match value.trim().parse::<usize>() {
+                Ok(val) if val > 0 => Some(val),
+                _ => {
+                    warn!("Invalid level of detail count, exporting single mesh");
+                    None
+                }
+            },
+            None => None,
+        };
+        let lod_error = match matches.value_of("lod_error") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val > 0.0 => val,
+                _ => {
+                    warn!("Invalid level of detail error, setting {}", DEF_LOD_ERROR);
+                    DEF_LOD_ERROR
+                }
+            },
+            None => DEF_LOD_ERROR,
+        };
+        let colormap = match (matches.is_present("vertex_colors"), matches.value_of("colormap")) {
+            (false, _) => None,
+            (true, None) => Some(Colormap::Grayscale),
+            (true, Some(value)) => match Colormap::from_name(value) {
+                Some(colormap) => Some(colormap),
+                None => {
+                    warn!("Invalid colormap, setting grayscale");
+                    Some(Colormap::Grayscale)
+                }
+            },
+        };
+        ExportSettings {
+            format,
+            texture: None,
+            simplify,
+            solid: matches.is_present("solid"),
+            base_depth,
+            print_size,
+            scale,
+            up_axis,
+            center: matches.is_present("center"),
+            region,
+            lod_levels,
+            lod_error,
+            colormap,
+        }
+    }
+
+    pub fn new() -> ExportSettings {
+        ExportSettings {
+            format: None,
+            texture: None,
+            simplify: None,
+            solid: false,
+            base_depth: DEF_BASE_DEPTH,
+            print_size: None,
+            scale: None,
+            up_axis: UpAxis::Z,
+            center: false,
+            region: ExportRegion::Padded,
+            lod_levels: None,
+            lod_error: DEF_LOD_ERROR,
+            colormap: None,
+        }
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Mapping of distances to output values
+#[derive(Debug, Clone)]
+pub struct OutputSettings {
+    pub falloff: Falloff,
+    /// surface is white and far pixels black, reversed when false
+    pub invert: bool,
+    /// distance mapped to the end of the falloff, largest distance is used when not set
+    pub clamp_distance: Option<f32>,
+    /// custom mapping used instead of the falloff
+    pub expression: Option<Expr>,
+}
+
+impl OutputSettings {
+    pub fn new_from_input(matches: &ArgMatches) -> OutputSettings {
+        let falloff = match matches.value_of("falloff") {
+            Some(value) => match Falloff::from_name(value) {
+                Some(falloff) => falloff,
+                None => {
+                    warn!("Invalid falloff, setting linear");
+                    Falloff::Linear
+                }
+            },
+            None => Falloff::Linear,
+        };
+        let clamp_distance = match matches.value_of("clamp_distance") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val > 0.0 => Some(val),
+                _ => {
+                    warn!("Invalid clamp distance, using largest distance");
+                    None
+                }
+            },
+            None => None,
+        };
+        let expression = match matches.value_of("remap") {
+            Some(value) => match Expr::parse(value) {
+                Ok(expression) => Some(expression),
+                Err(error) => {
+                    warn!("Invalid remap expression ({}), using falloff", error);
+                    None
+                }
+            },
+            None => None,
+        };
+        OutputSettings {
+            falloff,
+            invert: !matches.is_present("not_inverted"),
+            clamp_distance,
+            expression,
+        }
+    }
+
+    pub fn new() -> OutputSettings {
+        OutputSettings {
+            falloff: Falloff::Linear,
+            invert: true,
+            clamp_distance: None,
+            expression: None,
+        }
+    }
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Corrections of the input done before mesh generation. Filters run first, median before
+/// gaussian, then values are inverted, leveled, gamma corrected and mapped by the curve.
+#[derive(Debug, Clone)]
+pub struct PreprocessSettings {
+    pub invert: bool,
+    /// black and white point, values between them are stretched to the whole range
+    pub levels: Option<(u8, u8)>,
+    /// output is input raised to `1 / gamma`, so values above 1 brighten midtones
+    pub gamma: Option<f32>,
+    /// control points of piecewise linear curve as (input, output) pairs sorted by input
+    pub curve: Option<Vec<(u8, u8)>>,
+    /// sigma of gaussian blur
+    pub blur: Option<f32>,
+    /// radius of median filter
+    pub median: Option<u32>,
+}
+
+impl PreprocessSettings {
+    pub fn new_from_input(matches: &ArgMatches) -> PreprocessSettings {
+        let levels = match matches.value_of("levels") {
+            Some(value) => {
+                let values: Vec<Option<u8>> = value.split(',').map(|level| level.trim().parse::<u8>().ok()).collect();
+                match values.as_slice() {
+                    [Some(black), Some(white)] if black < white => Some((*black, *white)),
+                    _ => {
+                        warn!("Invalid levels, keeping input levels");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let gamma = match matches.value_of("gamma") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val > 0.0 => Some(val),
+                _ => {
+                    warn!("Invalid gamma, setting 1.0");
+                    None
+                }
+            },
+            None => None,
+        };
+        let curve = match matches.value_of("curve") {
+            Some(value) => {
+                let points: Option<Vec<(u8, u8)>> = value
+                    .split(',')
+                    .map(|point| {
+                        let mut values = point.split(':').map(|value| value.trim().parse::<u8>().ok());
+                        match (values.next(), values.next(), values.next()) {
+                            (Some(Some(input)), Some(Some(output)), None) => Some((input, output)),
+                            _ => None,
+                        }
+                    }).collect();
+                match points {
+                    Some(mut points) => {
+                        points.sort_by_key(|point| point.0);
+                        Some(points)
+                    }
+                    None => {
+                        warn!("Invalid curve, keeping input values");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let blur = match matches.value_of("blur") {
+            Some(value) => match value.trim().parse::<f32>() {
+                Ok(val) if val > 0.0 => Some(val),
+                _ => {
+                    warn!("Invalid blur sigma, not blurring");
+                    None
+                }
+            },
+            None => None,
+        };
+        let median = match matches.value_of("median") {
+            Some(value) => match value.trim().parse::<u32>() {
+                Ok(val) if val > 0 => Some(val),
+                _ => {
+                    warn!("Invalid median radius, not filtering");
+                    None
+                }
+            },
+            None => None,
+        };
+        PreprocessSettings {
+            invert: matches.is_present("invert"),
+            levels,
+            gamma,
+            curve,
+            blur,
+            median,
+        }
+    }
+
+    pub fn new() -> PreprocessSettings {
+        PreprocessSettings {
+            invert: false,
+            levels: None,
+            gamma: None,
+            curve: None,
+            blur: None,
+            median: None,
+        }
+    }
+
+    /// Whether any preprocessing is done
+    pub fn is_active(&self) -> bool {
+        self.invert || self.levels.is_some() || self.gamma.is_some() || self.curve.is_some() || self.blur.is_some() || self.median.is_some()
+    }
+}
+
+impl Default for PreprocessSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+/// Exported part of the mesh, padded region includes the border used for repeating or clamping
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportRegion {
+    Core,
+    Padded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshFormat {
+    Obj,
+    Stl,
+    Ply,
+    Glb,
+}
+
+impl MeshFormat {
+    pub fn from_name(name: &str) -> Option<MeshFormat> {
+        match name.trim().to_lowercase().as_str() {
+            "obj" => Some(MeshFormat::Obj),
+            "stl" => Some(MeshFormat::Stl),
+            "ply" => Some(MeshFormat::Ply),
+            "glb" => Some(MeshFormat::Glb),
+            _ => None,
+        }
+    }
+
+    /// Guess format from extension of given filename
+    pub fn from_filename(filename: &str) -> Option<MeshFormat> {
+        Path::new(filename).extension().and_then(|ext| ext.to_str()).and_then(MeshFormat::from_name)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "obj",
+            MeshFormat::Stl => "stl",
+            MeshFormat::Ply => "ply",
+            MeshFormat::Glb => "glb",
+        }
+    }
 }
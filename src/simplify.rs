@@ -0,0 +1,154 @@
+use mesh::{GridRect, Mesh};
+use rayon;
+use trimesh::TriMesh;
+use vec3::Vec3;
+
+/// Rectangles with more vertices than this are subdivided in parallel
+const PARALLEL_AREA: usize = 128 * 128;
+
+/// Vertex grid of the mesh, heights only
+struct Grid<'a> {
+    mesh: &'a Mesh,
+    row: usize,
+}
+
+impl<'a> Grid<'a> {
+    fn height(&self, x: usize, y: usize) -> f32 {
+        self.mesh.verts[y * self.row + x].z
+    }
+}
+
+/// Simplify given part of the mesh vertex grid with a restricted quadtree triangulation.
+///
+/// Every quadtree leaf is approximated by two triangles over its corners, leaves are split until
+/// every sample inside is within half of `max_error` of that approximation. Leaves with finer
+/// neighbours are fanned around their center to stay crack-free, which can at most double the
+/// error, so the result never deviates from the grid by more than `max_error` vertically.
+pub fn simplify(mesh: &Mesh, rect: &GridRect, max_error: f32) -> TriMesh {
+    let (row, rows) = mesh.grid_size();
+    let grid = Grid { mesh, row };
+    let leaves = subdivide(&grid, *rect, max_error / 2.0);
+
+    // corners of all leaves end up in the mesh
+    let mut used = vec![false; row * rows];
+    for leaf in &leaves {
+        for (x, y) in &[(leaf.x0, leaf.y0), (leaf.x1, leaf.y0), (leaf.x1, leaf.y1), (leaf.x0, leaf.y1)] {
+            used[y * row + x] = true;
+        }
+    }
+    let mut indices: Vec<u32> = vec![0; row * rows];
+    let mut out = TriMesh::new();
+    for (i, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+        indices[i] = out.verts.len() as u32;
+        out.verts.push(mesh.verts[i].as_ref().clone());
+    }
+
+    let origin = (mesh.verts[0].x, mesh.verts[0].y);
+    for leaf in &leaves {
+        let perimeter = perimeter(leaf, &used, row);
+        let index = |(x, y): (usize, usize)| indices[y * row + x];
+        if perimeter.len() == 4 {
+            let (bl, br, tr, tl) = (index(perimeter[0]), index(perimeter[1]), index(perimeter[2]), index(perimeter[3]));
+            // same diagonal as the full resolution grid
+            out.faces.push([tr, tl, bl]);
+            out.faces.push([tr, bl, br]);
+        } else {
+            // center lies on the diagonal, so its height matches the two triangle approximation
+            let center = out.verts.len() as u32;
+            out.verts.push(Vec3::new((
+                origin.0 + (leaf.x0 + leaf.x1) as f32 / 2.0,
+                origin.1 + (leaf.y0 + leaf.y1) as f32 / 2.0,
+                (grid.height(leaf.x0, leaf.y0) + grid.height(leaf.x1, leaf.y1)) / 2.0,
+            )));
+            for i in 0..perimeter.len() {
+                let next = perimeter[(i + 1) % perimeter.len()];
+                out.faces.push([center, index(perimeter[i]), index(next)]);
+            }
+        }
+    }
+    out.uvs = mesh.texture_uvs(&out.verts);
+    out
+}
+
+/// Split rectangle until it is flat enough, returns leaves
+fn subdivide(grid: &Grid, rect: GridRect, max_error: f32) -> Vec<GridRect> {
+    let split_x = rect.x1 - rect.x0 >= 2;
+    let split_y = rect.y1 - rect.y0 >= 2;
+    if (!split_x && !split_y) || approximation_error(grid, &rect) <= max_error {
+        return vec![rect];
+    }
+    let (mx, my) = ((rect.x0 + rect.x1) / 2, (rect.y0 + rect.y1) / 2);
+    let xs = if split_x { vec![(rect.x0, mx), (mx, rect.x1)] } else { vec![(rect.x0, rect.x1)] };
+    let ys = if split_y { vec![(rect.y0, my), (my, rect.y1)] } else { vec![(rect.y0, rect.y1)] };
+    let mut children = Vec::with_capacity(4);
+    for (y0, y1) in &ys {
+        for (x0, x1) in &xs {
+            children.push(GridRect {
+                x0: *x0,
+                y0: *y0,
+                x1: *x1,
+                y1: *y1,
+            });
+        }
+    }
+
+    let area = (rect.x1 - rect.x0 + 1) * (rect.y1 - rect.y0 + 1);
+    if area < PARALLEL_AREA {
+        return children.into_iter().flat_map(|child| subdivide(grid, child, max_error)).collect();
+    }
+    let half = children.len() / 2;
+    let (first, second) = children.split_at(half);
+    let (mut first, second) = rayon::join(
+        || first.iter().flat_map(|child| subdivide(grid, *child, max_error)).collect::<Vec<GridRect>>(),
+        || second.iter().flat_map(|child| subdivide(grid, *child, max_error)).collect::<Vec<GridRect>>(),
+    );
+    first.extend(second);
+    first
+}
+
+/// Maximal vertical distance of samples inside the rectangle from two triangles over its corners
+fn approximation_error(grid: &Grid, rect: &GridRect) -> f32 {
+    let bl = grid.height(rect.x0, rect.y0);
+    let br = grid.height(rect.x1, rect.y0);
+    let tr = grid.height(rect.x1, rect.y1);
+    let tl = grid.height(rect.x0, rect.y1);
+    let width = (rect.x1 - rect.x0) as f32;
+    let height = (rect.y1 - rect.y0) as f32;
+    let mut error: f32 = 0.0;
+    for y in rect.y0..=rect.y1 {
+        let v = (y - rect.y0) as f32 / height;
+        for x in rect.x0..=rect.x1 {
+            let u = (x - rect.x0) as f32 / width;
+            let approx = if v <= u {
+                bl + u * (br - bl) + v * (tr - br)
+            } else {
+                bl + v * (tl - bl) + u * (tr - tl)
+            };
+            error = error.max((approx - grid.height(x, y)).abs());
+        }
+    }
+    error
+}
+
+/// Used vertices on the rectangle border, counter-clockwise from bottom left corner
+fn perimeter(rect: &GridRect, used: &[bool], row: usize) -> Vec<(usize, usize)> {
+    let mut points = Vec::new();
+    let mut push = |x: usize, y: usize| {
+        if used[y * row + x] {
+            points.push((x, y));
+        }
+    };
+    for x in rect.x0..rect.x1 {
+        push(x, rect.y0);
+    }
+    for y in rect.y0..rect.y1 {
+        push(rect.x1, y);
+    }
+    for x in ((rect.x0 + 1)..=rect.x1).rev() {
+        push(x, rect.y1);
+    }
+    for y in ((rect.y0 + 1)..=rect.y1).rev() {
+        push(rect.x0, y);
+    }
+    points
+}
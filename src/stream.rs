@@ -0,0 +1,312 @@
+use channel::ChannelSource;
+use deflate::write::ZlibEncoder;
+use deflate::Compression;
+use extrema::Extrema;
+use generator;
+use image::Rgba;
+use mesh::Mesh;
+use png::{self, HasParameters};
+use preprocess;
+use remap;
+use settings::{GenSettings, ImgRepeat};
+use std::collections::VecDeque;
+use std::f32;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::time::Instant;
+
+/// Size of compressed data written out at once
+const IDAT_SIZE: usize = 1 << 16;
+
+/// Reads PNG image row by row as height values, with channel selection and value corrections
+/// from settings applied. Blur and median filter are not applied.
+pub struct RowReader {
+    reader: png::Reader<BufReader<File>>,
+    pub dim: (usize, usize),
+    channels: usize,
+    source: ChannelSource,
+    lut: [u8; 256],
+}
+
+impl RowReader {
+    pub fn open(filename: &str, settings: &GenSettings) -> io::Result<RowReader> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(filename)?));
+        // palette, transparency and low bit depths are expanded to 8 bit samples, 16 bit samples keep their high byte
+        decoder.set(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        // info has color type and bit depth of transformed rows
+        let (info, reader) = decoder.read_info()?;
+        if reader.info().interlaced {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "interlaced images can't be streamed"));
+        }
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "only 8 bit samples can be streamed"));
+        }
+        Ok(RowReader {
+            reader,
+            dim: (info.width as usize, info.height as usize),
+            channels: info.color_type.samples(),
+            source: settings.channel.unwrap_or(ChannelSource::Red),
+            lut: preprocess::lookup_table(&settings.preprocess),
+        })
+    }
+
+    /// Next row of the image, rows go from the top
+    pub fn next_row(&mut self) -> io::Result<Vec<u8>> {
+        let (channels, source, lut) = (self.channels, self.source, &self.lut);
+        match self.reader.next_row()? {
+            Some(row) => Ok(row.chunks(channels).map(|pixel| lut[source.value(&to_rgba(pixel)) as usize]).collect()),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "image has less rows than expected")),
+        }
+    }
+}
+
+/// Pixel of 8 bit PNG row with given number of samples
+fn to_rgba(pixel: &[u8]) -> Rgba<u8> {
+    match *pixel {
+        [v] => Rgba([v, v, v, 255]),
+        [v, a] => Rgba([v, v, v, a]),
+        [r, g, b] => Rgba([r, g, b, 255]),
+        _ => Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]),
+    }
+}
+
+/// Writes grayscale PNG image row by row, compressed data are written to the file as they come
+pub struct RowWriter {
+    encoder: ZlibEncoder<IdatWriter>,
+}
+
+impl RowWriter {
+    pub fn create(filename: &str, dim: (usize, usize)) -> io::Result<RowWriter> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(filename)?), dim.0 as u32, dim.1 as u32);
+        encoder.set(png::ColorType::Grayscale).set(png::BitDepth::Eight);
+        let writer = encoder.write_header()?;
+        Ok(RowWriter {
+            encoder: ZlibEncoder::new(
+                IdatWriter {
+                    writer,
+                    buffer: Vec::with_capacity(IDAT_SIZE),
+                },
+                Compression::Fast,
+            ),
+        })
+    }
+
+    pub fn write_row(&mut self, row: &[u8]) -> io::Result<()> {
+        // sub filter, every value is stored as difference to its left neighbour
+        let mut line = Vec::with_capacity(row.len() + 1);
+        line.push(1);
+        line.extend(row.iter().scan(0u8, |left, value| {
+            let diff = value.wrapping_sub(*left);
+            *left = *value;
+            Some(diff)
+        }));
+        self.encoder.write_all(&line)
+    }
+
+    /// Write remaining data, image end is written when the file is closed
+    pub fn finish(self) -> io::Result<()> {
+        self.encoder.finish()?.flush()
+    }
+}
+
+/// Splits compressed image data into IDAT chunks
+struct IdatWriter {
+    writer: png::Writer<BufWriter<File>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for IdatWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= IDAT_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.writer.write_chunk(*b"IDAT", &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Compute distance field of PNG image in square tiles of `band_rows`, only input rows of one band
+/// with an apron of usable radius around it are held in memory, meshes and distances are made for
+/// one tile at a time. Distances are kept in a temporary file next to the output until the largest
+/// of them is known, then the output is written row by row. The temporary file is removed on errors too.
+pub fn process(input: &str, output: &str, settings: &GenSettings, band_rows: usize) -> io::Result<()> {
+    let spill_name = format!("{}.part", output);
+    let result = process_with_spill(input, output, &spill_name, settings, band_rows);
+    let removed = match fs::remove_file(&spill_name) {
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        removed => removed,
+    };
+    result.and(removed)
+}
+
+fn process_with_spill(input: &str, output: &str, spill_name: &str, settings: &GenSettings, band_rows: usize) -> io::Result<()> {
+    // first pass finds extrema and keeps rows needed for padding at edges of the image
+    let mut reader = RowReader::open(input, settings)?;
+    let (width, height) = reader.dim;
+    let keep = settings.radius.min(height);
+    let mut ext = Extrema { min: 255, max: 0 };
+    let mut head: Vec<Vec<u8>> = Vec::with_capacity(keep);
+    let mut tail: VecDeque<Vec<u8>> = VecDeque::with_capacity(keep + 1);
+    for y in 0..height {
+        let row = reader.next_row()?;
+        for value in &row {
+            ext.min = ext.min.min(*value);
+            ext.max = ext.max.max(*value);
+        }
+        if y < keep {
+            head.push(row.clone());
+        }
+        tail.push_back(row);
+        if tail.len() > keep {
+            tail.pop_front();
+        }
+    }
+    let apron = Mesh::max_radius(&ext, settings);
+    // padding of tile mesh is limited by its size
+    let band = band_rows.max(apron).min(height);
+    let tile = band_rows.max(apron).min(width);
+    info!("Streaming {} rows in bands of {} and tiles of {} columns, apron is {}", height, band, tile, apron);
+
+    // tile meshes are padded like repeated images, edges of the image are handled when sampling
+    let mut tile_settings = settings.clone();
+    tile_settings.repeat = ImgRepeat::Repeat;
+    let (w, h) = (width as isize, height as isize);
+    let mut spill = BufWriter::new(File::create(spill_name)?);
+    let mut max = 0.0f32;
+    let mut reader = RowReader::open(input, settings)?;
+    let mut window: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut window_start = 0;
+    let mut written = 0;
+    while written < height {
+        let now = Instant::now();
+        // last band is moved up to keep its height, rows computed twice are written once
+        let start = written.min(height - band);
+        while window_start + window.len() < (start + band + apron).min(height) {
+            window.push_back(reader.next_row()?);
+        }
+        while window_start + apron < start {
+            window.pop_front();
+            window_start += 1;
+        }
+        let mut written_columns = 0;
+        while written_columns < width {
+            // last tile is moved left the same way
+            let left = written_columns.min(width - tile);
+            let mesh = Mesh::generate_sampled((tile, band), &ext, &tile_settings, |x, y| {
+                // mesh y grows up, image rows grow down
+                let (x, y) = ((left as isize) + x, (start + band) as isize - 1 - y);
+                let (x, y) = match settings.repeat {
+                    ImgRepeat::Repeat => (x.rem_euclid(w), y.rem_euclid(h)),
+                    ImgRepeat::Clamp => (x.max(0).min(w - 1), y.max(0).min(h - 1)),
+                };
+                let (x, y) = (x as usize, y as usize);
+                if y >= window_start && y < window_start + window.len() {
+                    window[y - window_start][x]
+                } else if y < head.len() {
+                    head[y][x]
+                } else {
+                    tail[y + tail.len() - height][x]
+                }
+            });
+            let distances = generator::generate_distances(&mesh, &tile_settings, &ext);
+            for row in (written - start)..band {
+                spill.seek(SeekFrom::Start((((start + row) * width + written_columns) * 4) as u64))?;
+                // distances go from the bottom row up
+                let offset = (band - 1 - row) * tile;
+                for dist in &distances[offset + written_columns - left..offset + tile] {
+                    if dist.dst < f32::MAX {
+                        max = max.max(dist.dst);
+                    }
+                    spill.write_all(&dist.dst.to_le_bytes())?;
+                }
+            }
+            written_columns = left + tile;
+        }
+        written = start + band;
+        let time = now.elapsed();
+        info!("Rows up to {} computed in {}", written, time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9);
+    }
+    spill.flush()?;
+
+    let mut spill = BufReader::new(File::open(spill_name)?);
+    let mut writer = RowWriter::create(output, (width, height))?;
+    let mut bytes = vec![0u8; width * 4];
+    for _ in 0..height {
+        spill.read_exact(&mut bytes)?;
+        let row: Vec<u8> = bytes
+            .chunks(4)
+            .map(|b| {
+                let dst = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                remap::distance_to_byte(&settings.output, dst.min(max), max)
+            }).collect();
+        writer.write_row(&row)?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{self, DynamicImage, ImageBuffer, Luma};
+    use settings::CaptureHeight;
+    use std::env;
+    use std::process;
+
+    fn temp_filename(name: &str) -> String {
+        env::temp_dir().join(format!("distance_field_{}_{}", process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn values(x: u32, y: u32) -> u8 {
+        // pit lower than the border, its nearest vertices are farther than the border heights allow
+        if (x as i32 - 50).pow(2) + (y as i32 - 22).pow(2) < 60 {
+            10
+        } else {
+            ((x * 37 + y * 91 + x * y) % 90 + 120) as u8
+        }
+    }
+
+    #[test]
+    fn streamed_image_matches_image_computed_in_memory() {
+        let img = DynamicImage::ImageLuma8(ImageBuffer::from_fn(64, 48, |x, y| Luma([values(x, y)])));
+        let input = temp_filename("stream_input.png");
+        img.save(&input).unwrap();
+        for repeat in [ImgRepeat::Repeat, ImgRepeat::Clamp] {
+            let settings = GenSettings::new_from_values((16, repeat, CaptureHeight::Generated, 1.0));
+            let ext = Extrema::get_image_extrema(&img);
+            let mesh = Mesh::generate(&img, &settings);
+            let expected = generator::generate_image(mesh.dimensions, &generator::generate_distances(&mesh, &settings, &ext), &settings);
+            for band_rows in [4, 16, 64] {
+                let output = temp_filename("stream_output.png");
+                process(&input, &output, &settings, band_rows).unwrap();
+                let streamed = image::open(&output).unwrap().to_luma();
+                fs::remove_file(&output).unwrap();
+                assert_eq!(streamed.into_raw(), expected.clone().into_raw(), "{:?} in bands of {} rows", repeat, band_rows);
+            }
+        }
+        fs::remove_file(&input).unwrap();
+    }
+
+    #[test]
+    fn sixteen_bit_samples_are_read_as_their_high_byte() {
+        let input = temp_filename("stream_sixteen.png");
+        {
+            let mut encoder = png::Encoder::new(File::create(&input).unwrap(), 3, 2);
+            encoder.set(png::ColorType::Grayscale).set(png::BitDepth::Sixteen);
+            let samples: Vec<u8> = (0..6u16).flat_map(|i| (i * 0x2a11).to_be_bytes()).collect();
+            encoder.write_header().unwrap().write_image_data(&samples).unwrap();
+        }
+        let mut reader = RowReader::open(&input, &GenSettings::new()).unwrap();
+        let rows = (reader.next_row().unwrap(), reader.next_row().unwrap());
+        fs::remove_file(&input).unwrap();
+        assert_eq!(reader.dim, (3, 2));
+        assert_eq!(rows, (vec![0x00, 0x2a, 0x54], vec![0x7e, 0xa8, 0xd2]));
+    }
+}
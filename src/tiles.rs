@@ -0,0 +1,151 @@
+use extrema::Extrema;
+use image::{GrayImage, ImageError, ImageResult};
+use mesh::Mesh;
+use settings::GenSettings;
+use std::path::Path;
+
+/// Grid of heightmap tiles of the same size. Column grows to the right and row grows down,
+/// like pixels of the image the tiles were cut from.
+pub struct TileGrid {
+    pub columns: usize,
+    pub rows: usize,
+    pub tile_dim: (usize, usize),
+    /// tiles row by row
+    tiles: Vec<GrayImage>,
+}
+
+/// Filename of the tile, `{x}` in the pattern is replaced by column and `{y}` by row
+pub fn tile_filename(pattern: &str, column: usize, row: usize) -> String {
+    pattern.replace("{x}", &column.to_string()).replace("{y}", &row.to_string())
+}
+
+/// Number of columns and rows of existing tiles, counted from the first tile
+pub fn grid_size(pattern: &str) -> (usize, usize) {
+    let columns = (0..).take_while(|x| Path::new(&tile_filename(pattern, *x, 0)).exists()).count();
+    let rows = (0..).take_while(|y| Path::new(&tile_filename(pattern, 0, *y)).exists()).count();
+    (columns, rows)
+}
+
+impl TileGrid {
+    /// Grid from tiles given row by row, all of them have to have the same size
+    pub fn new(columns: usize, rows: usize, tiles: Vec<GrayImage>) -> ImageResult<TileGrid> {
+        if tiles.len() != columns * rows || tiles.is_empty() {
+            return Err(ImageError::DimensionError);
+        }
+        let dim = tiles[0].dimensions();
+        if tiles.iter().any(|tile| tile.dimensions() != dim) {
+            return Err(ImageError::DimensionError);
+        }
+        Ok(TileGrid {
+            columns,
+            rows,
+            tile_dim: (dim.0 as usize, dim.1 as usize),
+            tiles,
+        })
+    }
+
+    /// Extrema of all tiles, shared so every tile uses the same capture height
+    pub fn extrema(&self) -> Extrema {
+        let mut e = Extrema { min: 255, max: 0 };
+        for pixel in self.tiles.iter().flat_map(|tile| tile.pixels()) {
+            e.min = e.min.min(pixel.data[0]);
+            e.max = e.max.max(pixel.data[0]);
+        }
+        e
+    }
+
+    /// Mesh of one tile, its padding is filled from neighbouring tiles and clamped at edges of the grid
+    pub fn mesh(&self, column: usize, row: usize, ext: &Extrema, settings: &GenSettings) -> Mesh {
+        let (w, h) = (self.tile_dim.0 as isize, self.tile_dim.1 as isize);
+        Mesh::generate_sampled(self.tile_dim, ext, settings, |x, y| {
+            // mesh y grows up, image rows grow down
+            let gx = (column as isize * w + x).max(0).min(self.columns as isize * w - 1);
+            let gy = (row as isize * h + h - 1 - y).max(0).min(self.rows as isize * h - 1);
+            let tile = &self.tiles[(gy / h) as usize * self.columns + (gx / w) as usize];
+            tile.get_pixel((gx % w) as u32, (gy % h) as u32).data[0]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generator;
+    use image::{DynamicImage, ImageBuffer, Luma};
+    use settings::{CaptureHeight, ImgRepeat};
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn image() -> GrayImage {
+        ImageBuffer::from_fn(24, 20, |x, y| Luma([((x * 37 + y * 91 + x * y) % 200 + 40) as u8]))
+    }
+
+    /// Tiles of the image, row by row
+    fn cut(img: &GrayImage, columns: u32, rows: u32) -> Vec<GrayImage> {
+        let (w, h) = (img.width() / columns, img.height() / rows);
+        let mut tiles = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                tiles.push(ImageBuffer::from_fn(w, h, |x, y| *img.get_pixel(column * w + x, row * h + y)));
+            }
+        }
+        tiles
+    }
+
+    #[test]
+    fn filenames_and_grid_size() {
+        assert_eq!(tile_filename("tile_{x}_{y}.png", 2, 13), "tile_2_13.png");
+        let pattern = env::temp_dir().join(format!("distance_field_{}_tile_{{x}}_{{y}}", process::id())).to_string_lossy().into_owned();
+        let files: Vec<String> = [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1), (3, 1)]
+            .iter()
+            .map(|&(x, y)| tile_filename(&pattern, x, y))
+            .collect();
+        for file in &files {
+            fs::write(file, b"").unwrap();
+        }
+        let size = grid_size(&pattern);
+        for file in &files {
+            fs::remove_file(file).unwrap();
+        }
+        assert_eq!(size, (3, 2));
+    }
+
+    #[test]
+    fn tiles_have_to_fill_the_grid_with_the_same_size() {
+        let tiles = cut(&image(), 2, 2);
+        assert!(TileGrid::new(2, 2, tiles.clone()).is_ok());
+        assert!(TileGrid::new(3, 2, tiles.clone()).is_err());
+        assert!(TileGrid::new(0, 0, Vec::new()).is_err());
+        let mut uneven = tiles;
+        uneven[3] = ImageBuffer::new(12, 9);
+        assert!(TileGrid::new(2, 2, uneven).is_err());
+    }
+
+    #[test]
+    fn tiles_match_the_whole_image() {
+        let img = image();
+        let grid = TileGrid::new(2, 2, cut(&img, 2, 2)).unwrap();
+        let ext = grid.extrema();
+        let whole = DynamicImage::ImageLuma8(img.clone());
+        let whole_ext = Extrema::get_image_extrema(&whole);
+        assert_eq!((ext.min, ext.max), (whole_ext.min, whole_ext.max));
+
+        let settings = GenSettings::new_from_values((4, ImgRepeat::Clamp, CaptureHeight::UserDefined(250), 1.0));
+        let expected = generator::generate_distances(&Mesh::generate(&whole, &settings), &settings, &ext);
+        let mut tile_settings = settings.clone();
+        tile_settings.repeat = ImgRepeat::Repeat;
+        let (width, height) = (img.width() as isize, img.height() as isize);
+        for row in 0..2 {
+            for column in 0..2 {
+                let distances = generator::generate_distances(&grid.mesh(column, row, &ext, &tile_settings), &tile_settings, &ext);
+                let (w, h) = (grid.tile_dim.0 as isize, grid.tile_dim.1 as isize);
+                for dist in &distances {
+                    // mesh y of the tile grows up from the bottom row of the tile
+                    let (x, y) = (column as isize * w + dist.x, height - (row as isize + 1) * h + dist.y);
+                    assert_eq!(dist.dst, expected[(y * width + x) as usize].dst, "tile {}, {} at {}, {}", column, row, dist.x, dist.y);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::f32;
+use vec3::Vec3;
+
+/// Indexed triangle mesh, used as the common input of all mesh writers.
+/// Normals, uvs and colors are optional, when present they have one entry per vertex.
+#[derive(Debug, Clone)]
+pub struct TriMesh {
+    pub verts: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    /// srgb in 0-1 range, like colormaps give them
+    pub colors: Vec<[f32; 3]>,
+    pub faces: Vec<[u32; 3]>,
+}
+
+impl TriMesh {
+    pub fn new() -> TriMesh {
+        TriMesh {
+            verts: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            faces: Vec::new(),
+        }
+    }
+
+    /// Multiply all vertex coordinates by given factor
+    pub fn scale(&mut self, factor: f32) {
+        for vert in &mut self.verts {
+            vert.x *= factor;
+            vert.y *= factor;
+            vert.z *= factor;
+        }
+    }
+
+    /// Move all vertices by given offset
+    pub fn translate(&mut self, offset: (f32, f32, f32)) {
+        for vert in &mut self.verts {
+            vert.x += offset.0;
+            vert.y += offset.1;
+            vert.z += offset.2;
+        }
+    }
+
+    /// Rotate mesh from Z-up to Y-up convention, keeps handedness and winding
+    pub fn to_y_up(&mut self) {
+        for vert in self.verts.iter_mut().chain(self.normals.iter_mut()) {
+            let y = vert.y;
+            vert.y = vert.z;
+            vert.z = -y;
+        }
+    }
+
+    /// Returns minimal and maximal corner of axis aligned bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let mut min = Vec3::new((f32::MAX, f32::MAX, f32::MAX));
+        let mut max = Vec3::new((f32::MIN, f32::MIN, f32::MIN));
+        for vert in &self.verts {
+            min = Vec3::new((min.x.min(vert.x), min.y.min(vert.y), min.z.min(vert.z)));
+            max = Vec3::new((max.x.max(vert.x), max.y.max(vert.y), max.z.max(vert.z)));
+        }
+        (min, max)
+    }
+
+    /// Close the surface into a solid: side walls go down from its boundary to a flat base
+    /// `depth` under the lowest vertex. The boundary has to be a single loop which is star-shaped
+    /// around its center, which holds for heightfields. Normals have to be computed again.
+    pub fn solidify(&mut self, depth: f32) {
+        let (min, max) = self.bounds();
+        let base = min.z - depth;
+        let boundary = self.boundary_edges();
+        let base_verts = self.extrude_boundary(&boundary, |_| base);
+
+        let center = self.verts.len() as u32;
+        self.verts.push(Vec3::new(((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, base)));
+        if !self.uvs.is_empty() {
+            let sum = self.uvs.iter().fold((0.0, 0.0), |sum, uv| (sum.0 + uv.0, sum.1 + uv.1));
+            let count = self.uvs.len() as f32;
+            self.uvs.push((sum.0 / count, sum.1 / count));
+        }
+        if !self.colors.is_empty() {
+            let color = self.colors[boundary[0].0 as usize];
+            self.colors.push(color);
+        }
+        // base is a fan facing down
+        for (a, b) in &boundary {
+            self.faces.push([center, base_verts[b], base_verts[a]]);
+        }
+    }
+
+    /// Add a vertical skirt of given depth around the surface, hides cracks between neighbouring
+    /// meshes with different detail. Normals have to be computed again.
+    pub fn add_skirt(&mut self, depth: f32) {
+        let boundary = self.boundary_edges();
+        self.extrude_boundary(&boundary, |vert| vert.z - depth);
+    }
+
+    /// Directed edges used by only one face, they run counter-clockwise around the surface seen from above
+    fn boundary_edges(&self) -> Vec<(u32, u32)> {
+        let mut edges: HashSet<(u32, u32)> = HashSet::with_capacity(self.faces.len() * 3);
+        for face in &self.faces {
+            for i in 0..3 {
+                edges.insert((face[i], face[(i + 1) % 3]));
+            }
+        }
+        let mut boundary: Vec<(u32, u32)> = edges.iter().filter(|(a, b)| !edges.contains(&(*b, *a))).cloned().collect();
+        boundary.sort_unstable();
+        boundary
+    }
+
+    /// Copy boundary vertices down to height given by `bottom` and connect them with walls.
+    /// Returns indices of the copies.
+    fn extrude_boundary<F>(&mut self, boundary: &[(u32, u32)], bottom: F) -> HashMap<u32, u32>
+    where
+        F: Fn(&Vec3) -> f32,
+    {
+        let mut copies: HashMap<u32, u32> = HashMap::new();
+        for (a, _) in boundary {
+            let index = self.verts.len() as u32;
+            let vert = Vec3::new((self.verts[*a as usize].x, self.verts[*a as usize].y, bottom(&self.verts[*a as usize])));
+            self.verts.push(vert);
+            if !self.uvs.is_empty() {
+                let uv = self.uvs[*a as usize];
+                self.uvs.push(uv);
+            }
+            if !self.colors.is_empty() {
+                let color = self.colors[*a as usize];
+                self.colors.push(color);
+            }
+            copies.insert(*a, index);
+        }
+        for (a, b) in boundary {
+            let (a_low, b_low) = (copies[a], copies[b]);
+            // wall quad, uses the boundary edge in opposite direction
+            self.faces.push([*b, *a, a_low]);
+            self.faces.push([*b, a_low, b_low]);
+        }
+        self.normals.clear();
+        copies
+    }
+
+    /// Compute smooth vertex normals, faces contribute by their area
+    pub fn compute_normals(&mut self) {
+        let mut sums = vec![(0.0, 0.0, 0.0); self.verts.len()];
+        for face in &self.faces {
+            let a = &self.verts[face[0] as usize];
+            let b = &self.verts[face[1] as usize];
+            let c = &self.verts[face[2] as usize];
+            // length of cross product is twice the face area
+            let normal = (b - a).cross(&(c - a));
+            for index in face {
+                let sum = &mut sums[*index as usize];
+                sum.0 += normal.x;
+                sum.1 += normal.y;
+                sum.2 += normal.z;
+            }
+        }
+        self.normals = sums
+            .into_iter()
+            .map(|sum| {
+                let normal = Vec3::new(sum);
+                if normal.len() > 0.0 {
+                    normal.normalized()
+                } else {
+                    Vec3::new((0.0, 0.0, 1.0))
+                }
+            }).collect();
+    }
+
+    /// Sample heights of the mesh seen from above on a regular grid, grid point (x, y) lies at
+    /// `origin + (x * step.0, y * step.1)`. Where more triangles cover one point the highest is used,
+    /// points outside of the mesh are `None`.
+    pub fn sample_heights(&self, origin: (f32, f32), step: (f32, f32), size: (usize, usize)) -> Vec<Option<f32>> {
+        // tolerance, so points on shared edges are not missed
+        const EPS: f32 = 1e-5;
+        let mut heights: Vec<Option<f32>> = vec![None; size.0 * size.1];
+        if size.0 == 0 || size.1 == 0 {
+            return heights;
+        }
+        for face in &self.faces {
+            let a = &self.verts[face[0] as usize];
+            let b = &self.verts[face[1] as usize];
+            let c = &self.verts[face[2] as usize];
+            let det = (b.y - c.y) * (a.x - c.x) + (c.x - b.x) * (a.y - c.y);
+            if det.abs() < f32::EPSILON {
+                // vertical or degenerate face
+                continue;
+            }
+            // grid points under bounding box of the face
+            let to_grid = |value: f32, origin: f32, step: f32| (value - origin) / step;
+            let x_min = to_grid(a.x.min(b.x).min(c.x), origin.0, step.0).ceil().max(0.0) as usize;
+            let x_max = to_grid(a.x.max(b.x).max(c.x), origin.0, step.0).floor();
+            let y_min = to_grid(a.y.min(b.y).min(c.y), origin.1, step.1).ceil().max(0.0) as usize;
+            let y_max = to_grid(a.y.max(b.y).max(c.y), origin.1, step.1).floor();
+            if x_max < 0.0 || y_max < 0.0 {
+                continue;
+            }
+            let x_max = (x_max as usize).min(size.0 - 1);
+            let y_max = (y_max as usize).min(size.1 - 1);
+            for y in y_min..=y_max {
+                let py = origin.1 + y as f32 * step.1;
+                for x in x_min..=x_max {
+                    let px = origin.0 + x as f32 * step.0;
+                    // barycentric coordinates
+                    let l1 = ((b.y - c.y) * (px - c.x) + (c.x - b.x) * (py - c.y)) / det;
+                    let l2 = ((c.y - a.y) * (px - c.x) + (a.x - c.x) * (py - c.y)) / det;
+                    let l3 = 1.0 - l1 - l2;
+                    if l1 < -EPS || l2 < -EPS || l3 < -EPS {
+                        continue;
+                    }
+                    let z = l1 * a.z + l2 * b.z + l3 * c.z;
+                    let height = &mut heights[y * size.0 + x];
+                    *height = Some(height.map_or(z, |h| h.max(z)));
+                }
+            }
+        }
+        heights
+    }
+
+    /// Highest point of the mesh seen from above over every cell of a regular grid, cell (x, y) spans
+    /// from `origin + (x * step.0, y * step.1)` to the next grid point. Every face is clipped to the cells
+    /// it overlaps, so faces and edges narrower than a cell are not missed. Empty cells are `None`.
+    pub fn max_heights(&self, origin: (f32, f32), step: (f32, f32), size: (usize, usize)) -> Vec<Option<f32>> {
+        let mut heights: Vec<Option<f32>> = vec![None; size.0 * size.1];
+        if size.0 == 0 || size.1 == 0 {
+            return heights;
+        }
+        let to_cell = |value: f32, origin: f32, step: f32, size: usize| (((value - origin) / step).floor().max(0.0) as usize).min(size - 1);
+        for face in &self.faces {
+            let triangle: Vec<Vec3> = face.iter().map(|index| self.verts[*index as usize].clone()).collect();
+            let (x_min, x_max) = triangle.iter().fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v.x), max.max(v.x)));
+            let (y_min, y_max) = triangle.iter().fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v.y), max.max(v.y)));
+            for y in to_cell(y_min, origin.1, step.1, size.1)..=to_cell(y_max, origin.1, step.1, size.1) {
+                let (bottom, top) = (origin.1 + y as f32 * step.1, origin.1 + (y + 1) as f32 * step.1);
+                for x in to_cell(x_min, origin.0, step.0, size.0)..=to_cell(x_max, origin.0, step.0, size.0) {
+                    let (left, right) = (origin.0 + x as f32 * step.0, origin.0 + (x + 1) as f32 * step.0);
+                    let mut part = clip_polygon(&triangle, |v| v.x - left);
+                    part = clip_polygon(&part, |v| right - v.x);
+                    part = clip_polygon(&part, |v| v.y - bottom);
+                    part = clip_polygon(&part, |v| top - v.y);
+                    // height is linear over the face, so the highest point is a corner of the clipped part
+                    if let Some(z) = part.iter().map(|v| v.z).fold(None, |max: Option<f32>, z| Some(max.map_or(z, |max| max.max(z)))) {
+                        let height = &mut heights[y * size.0 + x];
+                        *height = Some(height.map_or(z, |h| h.max(z)));
+                    }
+                }
+            }
+        }
+        heights
+    }
+
+    /// Unit normal of given face, counter-clockwise winding faces outwards
+    pub fn face_normal(&self, face: &[u32; 3]) -> Vec3 {
+        let a = &self.verts[face[0] as usize];
+        let b = &self.verts[face[1] as usize];
+        let c = &self.verts[face[2] as usize];
+        let normal = (b - a).cross(&(c - a));
+        if normal.len() > 0.0 {
+            normal.normalized()
+        } else {
+            normal
+        }
+    }
+}
+
+impl Default for TriMesh {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Part of convex polygon where linear function `side` is not negative
+fn clip_polygon<F: Fn(&Vec3) -> f32>(polygon: &[Vec3], side: F) -> Vec<Vec3> {
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, current) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        let (s0, s1) = (side(current), side(next));
+        if s0 >= 0.0 {
+            clipped.push(current.clone());
+        }
+        if (s0 >= 0.0) != (s1 >= 0.0) {
+            clipped.push(current + &((next - current) * (s0 / (s0 - s1))));
+        }
+    }
+    clipped
+}
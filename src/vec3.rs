@@ -1,6 +1,6 @@
 use std::cmp::Ordering;
 use std::fmt;
-use std::ops::{Add, Index, Sub};
+use std::ops::{Add, Index, Mul, Sub};
 
 pub struct Vec3 {
     pub x: f32,
@@ -84,6 +84,25 @@ impl Vec3 {
     pub fn delta(&self, other: &Vec3) -> Vec3 {
         Vec3::new((self.x - other.x, self.y - other.y, self.z - other.z))
     }
+
+    /// Component-wise minimum
+    pub fn min(&self, other: &Vec3) -> Vec3 {
+        Vec3::new((self.x.min(other.x), self.y.min(other.y), self.z.min(other.z)))
+    }
+
+    /// Component-wise maximum
+    pub fn max(&self, other: &Vec3) -> Vec3 {
+        Vec3::new((self.x.max(other.x), self.y.max(other.y), self.z.max(other.z)))
+    }
+
+    /// Clamp every component to given range
+    pub fn clamp(&self, min: f32, max: f32) -> Vec3 {
+        Vec3::new((self.x.clamp(min, max), self.y.clamp(min, max), self.z.clamp(min, max)))
+    }
+
+    pub fn len_sqr(&self) -> f32 {
+        self.dot(self)
+    }
 }
 
 impl Clone for Vec3 {
@@ -104,7 +123,7 @@ impl PartialEq for Vec3 {
     }
 }
 
-impl<'a, 'b> Sub<&'b Vec3> for &'a Vec3 {
+impl<'b> Sub<&'b Vec3> for &Vec3 {
     type Output = Vec3;
     fn sub(self, other: &'b Vec3) -> Vec3 {
         Vec3::new((self.x - other.x, self.y - other.y, self.z - other.z))
@@ -118,13 +137,34 @@ impl Sub for Vec3 {
     }
 }
 
-impl<'a, 'b> Add<&'b Vec3> for &'a Vec3 {
+impl<'b> Add<&'b Vec3> for &Vec3 {
     type Output = Vec3;
     fn add(self, other: &'b Vec3) -> Vec3 {
         Vec3::new((self.x + other.x, self.y + other.y, self.z + other.z))
     }
 }
 
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new((self.x + other.x, self.y + other.y, self.z + other.z))
+    }
+}
+
+impl Mul<f32> for &Vec3 {
+    type Output = Vec3;
+    fn mul(self, scalar: f32) -> Vec3 {
+        Vec3::new((self.x * scalar, self.y * scalar, self.z * scalar))
+    }
+}
+
+impl Mul<f32> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, scalar: f32) -> Vec3 {
+        Vec3::new((self.x * scalar, self.y * scalar, self.z * scalar))
+    }
+}
+
 impl Index<usize> for Vec3 {
     type Output = f32;
     fn index(&self, num: usize) -> &f32 {
@@ -0,0 +1,81 @@
+use image::{ImageBuffer, ImageLuma8, Luma};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use vec3::Vec3;
+
+/// Regular grid of signed distances, negative values are inside.
+/// Voxel (x, y, z) has its center at `origin + (x, y, z) * spacing`, componentwise.
+#[derive(Debug, Clone)]
+pub struct Volume {
+    pub dims: (usize, usize, usize),
+    pub origin: Vec3,
+    pub spacing: Vec3,
+    /// x changes fastest, then y, then z
+    pub data: Vec<f32>,
+}
+
+impl Volume {
+    pub fn new(dims: (usize, usize, usize), origin: Vec3, spacing: Vec3) -> Volume {
+        Volume {
+            dims,
+            origin,
+            spacing,
+            data: vec![0.0; dims.0 * dims.1 * dims.2],
+        }
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (z * self.dims.1 + y) * self.dims.0 + x
+    }
+
+    /// Center of given voxel
+    pub fn position(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        Vec3::new((
+            self.origin.x + x as f32 * self.spacing.x,
+            self.origin.y + y as f32 * self.spacing.y,
+            self.origin.z + z as f32 * self.spacing.z,
+        ))
+    }
+
+    /// Write raw little endian floats together with a MetaImage header, so the volume
+    /// can be opened by common viewers. Returns name of the header file.
+    pub fn write_raw(&self, filename: &str) -> io::Result<String> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        for value in &self.data {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()?;
+
+        let header_name = Path::new(filename).with_extension("mhd").to_string_lossy().into_owned();
+        let raw_name = Path::new(filename).file_name().unwrap().to_string_lossy().into_owned();
+        let mut header = BufWriter::new(File::create(&header_name)?);
+        writeln!(header, "ObjectType = Image")?;
+        writeln!(header, "NDims = 3")?;
+        writeln!(header, "DimSize = {} {} {}", self.dims.0, self.dims.1, self.dims.2)?;
+        writeln!(header, "ElementSpacing = {} {} {}", self.spacing.x, self.spacing.y, self.spacing.z)?;
+        writeln!(header, "Offset = {} {} {}", self.origin.x, self.origin.y, self.origin.z)?;
+        writeln!(header, "ElementType = MET_FLOAT")?;
+        writeln!(header, "BinaryData = True")?;
+        writeln!(header, "BinaryDataByteOrderMSB = False")?;
+        writeln!(header, "ElementDataFile = {}", raw_name)?;
+        header.flush()?;
+        Ok(header_name)
+    }
+
+    /// Write every z slice as a grayscale PNG named `prefix_NNNN.png`. Zero distance maps to
+    /// middle gray, largest absolute distance to black inside and white outside.
+    pub fn write_slices(&self, prefix: &str) -> io::Result<()> {
+        let max = self.data.iter().fold(0.0f32, |max, value| max.max(value.abs())).max(f32::EPSILON);
+        for z in 0..self.dims.2 {
+            let mut imgbuf = ImageBuffer::new(self.dims.0 as u32, self.dims.1 as u32);
+            for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+                // image rows go from top, volume y from bottom
+                let value = self.data[self.index(x as usize, self.dims.1 - 1 - y as usize, z)];
+                *pixel = Luma([(127.5 + value / max * 127.5).round() as u8]);
+            }
+            ImageLuma8(imgbuf).save(format!("{}_{:04}.png", prefix, z))?;
+        }
+        Ok(())
+    }
+}